@@ -9,6 +9,13 @@ use opendal::services::S3Config;
 pub struct IotConfig {
     pub database_url: String,
     pub s3: S3Config,
+    /// How long to wait for the in-flight requests on shutdown, in seconds.
+    #[serde(default = "default_shutdown_timeout_secs")]
+    pub shutdown_timeout_secs: u64,
+}
+
+fn default_shutdown_timeout_secs() -> u64 {
+    10
 }
 
 pub fn parse_config() -> anyhow::Result<IotConfig> {
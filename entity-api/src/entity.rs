@@ -1,7 +1,10 @@
 use std::time::Duration;
 
-use crate::{query::Monitor, storage::Storage};
-use async_graphql::SimpleObject;
+use crate::{
+    query::{Monitor, MonitorLoader},
+    storage::Storage,
+};
+use async_graphql::{SimpleObject, dataloader::DataLoader};
 
 /// A detected entity.
 #[derive(SimpleObject)]
@@ -11,6 +14,8 @@ pub struct Entity {
     pub id: i32,
     #[graphql(skip)]
     pub image_id: String,
+    #[graphql(skip)]
+    pub clip_id: Option<String>,
     /// The label of the entity.
     pub label: String,
     /// The confidence of the entity.
@@ -22,6 +27,10 @@ pub struct Entity {
     pub monitor_id: Option<String>,
     /// The time when the entity was detected.
     pub created_at: chrono::DateTime<chrono::Utc>,
+    /// The version of the model which detected the entity.
+    ///
+    /// It is [`None`] for the entities detected before the model versions were recorded.
+    pub model_version: Option<String>,
 }
 
 static EXPIRE_AT: Duration = Duration::from_secs(3600);
@@ -42,9 +51,39 @@ impl Entity {
         Ok(image.uri().to_string())
     }
 
-    pub async fn monitor(&self) -> Monitor {
-        Monitor {
-            id: self.monitor_id.clone(),
-        }
+    /// Get the URL of the video clip around the detection, in MP4.
+    ///
+    /// It is [`None`] if the monitor does not record, or the clip is not exported yet.
+    /// Like [`Entity::url`], it expires in 1 hour.
+    pub async fn clip_url(
+        &self,
+        context: &async_graphql::Context<'_>,
+    ) -> async_graphql::Result<Option<String>> {
+        let Some(clip_id) = &self.clip_id else {
+            return Ok(None);
+        };
+
+        let storage = context.data::<Storage>()?;
+        let path = format!("/{clip_id}");
+        let clip = storage.presign_read(&path, EXPIRE_AT).await?;
+
+        Ok(Some(clip.uri().to_string()))
+    }
+
+    pub async fn monitor(
+        &self,
+        context: &async_graphql::Context<'_>,
+    ) -> async_graphql::Result<Monitor> {
+        let Some(monitor_id) = &self.monitor_id else {
+            return Ok(Monitor::default());
+        };
+
+        let loader = context.data::<DataLoader<MonitorLoader>>()?;
+        let monitor = loader.load_one(monitor_id.clone()).await?;
+
+        Ok(monitor.unwrap_or(Monitor {
+            id: Some(monitor_id.clone()),
+            ..Default::default()
+        }))
     }
 }
@@ -7,15 +7,16 @@ pub(crate) mod query;
 pub(crate) mod storage;
 
 use anyhow::Context as _;
-use async_graphql::{EmptySubscription, Schema, http::GraphiQLSource};
+use async_graphql::{EmptySubscription, Schema, dataloader::DataLoader, http::GraphiQLSource};
 use async_graphql_poem::GraphQL;
+use iot_common::shutdown::shutdown_signal;
 use mutation::MutationRoot;
 use poem::{IntoResponse, Route, Server, get, handler, listener::TcpListener, web::Html};
 use prelude::DatabasePool;
-use query::QueryRoot;
+use query::{MonitorLoader, QueryRoot};
 use storage::Storage;
 
-use std::{net::SocketAddr, str::FromStr};
+use std::{net::SocketAddr, str::FromStr, time::Duration};
 
 #[handler]
 async fn graphiql() -> impl IntoResponse {
@@ -37,8 +38,11 @@ async fn main() -> anyhow::Result<()> {
     let addr = SocketAddr::from_str(&std::env::var("BIND_ADDR").unwrap_or("0.0.0.0:8080".into()))
         .context("Invalid BIND_ADDR")?;
 
+    let monitor_loader = DataLoader::new(MonitorLoader::new(pool.get_pool()), tokio::spawn);
+
     let schema = Schema::build(QueryRoot, MutationRoot, EmptySubscription)
         .data(pool)
+        .data(monitor_loader)
         .data(storage)
         .finish();
 
@@ -49,7 +53,14 @@ async fn main() -> anyhow::Result<()> {
         port = addr.port()
     );
 
-    Server::new(TcpListener::bind(addr)).run(app).await.unwrap();
+    Server::new(TcpListener::bind(addr))
+        .run_with_graceful_shutdown(
+            app,
+            shutdown_signal(),
+            Some(Duration::from_secs(config.shutdown_timeout_secs)),
+        )
+        .await
+        .context("Failed to run the server")?;
 
     Ok(())
 }
@@ -29,7 +29,7 @@ impl MutationRoot {
             let image_bytes = image_resp.bytes().await?;
 
             let storage = context.data::<Storage>()?;
-            let image_id = format!("{}.jpg", Uuid::new_v4().to_string());
+            let image_id = format!("{}.jpg", Uuid::new_v4());
 
             let path = format!("/{image_id}");
             storage.write(&path, image_bytes).await?;
@@ -1,4 +1,7 @@
+use std::{collections::HashMap, sync::Arc};
+
 use async_graphql::SimpleObject;
+use async_graphql::dataloader::Loader;
 use async_graphql::types::connection::*;
 
 use crate::entity::Entity;
@@ -12,12 +15,15 @@ impl QueryRoot {
     async fn monitors(&self, context: &Context<'_>) -> async_graphql::Result<Vec<Monitor>> {
         let pool = context.data::<DatabasePool>()?.get_pool();
 
-        let mut monitors: Vec<_> = sqlx::query_as!(Monitor, "SELECT id FROM monitors")
-            .fetch_all(&pool)
-            .await?;
+        let mut monitors: Vec<_> = sqlx::query_as!(
+            Monitor,
+            "SELECT id, name, location, width, height, fps, codec, software_version, announced_at FROM monitors"
+        )
+        .fetch_all(&pool)
+        .await?;
 
         // Add a monitor with no ID to represent the entities without the monitor.
-        monitors.push(Monitor { id: None });
+        monitors.push(Monitor::default());
 
         Ok(monitors)
     }
@@ -31,11 +37,7 @@ impl QueryRoot {
     ) -> async_graphql::Result<Monitor> {
         let pool = context.data::<DatabasePool>()?.get_pool();
 
-        let monitor = sqlx::query_as!(Monitor, "SELECT id FROM monitors WHERE id = $1", id)
-            .fetch_optional(&pool)
-            .await?;
-
-        Ok(monitor.unwrap_or(Monitor { id }))
+        Ok(Monitor::find(&pool, id).await?)
     }
 
     /// Get an entity by ID.
@@ -44,7 +46,7 @@ impl QueryRoot {
 
         let entity = sqlx::query_as!(
             Entity,
-            "SELECT id, image_id, label, confidence, monitor_id, created_at FROM entities WHERE id = $1",
+            "SELECT id, image_id, clip_id, label, confidence, monitor_id, created_at, model_version FROM entities WHERE id = $1",
             id
         )
         .fetch_one(&pool)
@@ -54,13 +56,86 @@ impl QueryRoot {
     }
 }
 
-#[derive(SimpleObject)]
+#[derive(SimpleObject, Clone, Default)]
 #[graphql(complex)]
 pub struct Monitor {
     /// The ID of the monitor.
     ///
     /// [`Option::None`] means no monitor specified.
     pub id: Option<String>,
+
+    /// The display name of the monitor.
+    pub name: Option<String>,
+
+    /// Where the monitor is.
+    pub location: Option<String>,
+
+    /// The width of the stream in pixels.
+    pub width: Option<i32>,
+
+    /// The height of the stream in pixels.
+    pub height: Option<i32>,
+
+    /// The frame rate of the stream. `null` if it is variable.
+    pub fps: Option<f64>,
+
+    /// The codec of the stream, e.g. `H.264 (Main Profile)`.
+    pub codec: Option<String>,
+
+    /// The version of the stream extractor of the monitor.
+    pub software_version: Option<String>,
+
+    /// When the monitor was last announced by its stream extractor.
+    ///
+    /// `null` if it has never been announced, i.e. it is only known from its detections.
+    pub announced_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+impl Monitor {
+    /// Find a monitor by ID. A monitor not in the database has no details.
+    pub async fn find(pool: &sqlx::PgPool, id: Option<String>) -> sqlx::Result<Self> {
+        let monitor = sqlx::query_as!(
+            Monitor,
+            "SELECT id, name, location, width, height, fps, codec, software_version, announced_at FROM monitors WHERE id = $1",
+            id
+        )
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(monitor.unwrap_or(Monitor {
+            id,
+            ..Default::default()
+        }))
+    }
+}
+
+/// Load the monitors of many entities in one query, instead of one query per entity.
+pub struct MonitorLoader(sqlx::PgPool);
+
+impl MonitorLoader {
+    pub fn new(pool: sqlx::PgPool) -> Self {
+        Self(pool)
+    }
+}
+
+impl Loader<String> for MonitorLoader {
+    type Value = Monitor;
+    type Error = Arc<sqlx::Error>;
+
+    async fn load(&self, ids: &[String]) -> Result<HashMap<String, Monitor>, Self::Error> {
+        let monitors = sqlx::query_as!(
+            Monitor,
+            "SELECT id, name, location, width, height, fps, codec, software_version, announced_at FROM monitors WHERE id = ANY($1)",
+            ids
+        )
+        .fetch_all(&self.0)
+        .await?;
+
+        Ok(monitors
+            .into_iter()
+            .filter_map(|monitor| Some((monitor.id.clone()?, monitor)))
+            .collect())
+    }
 }
 
 #[async_graphql::ComplexObject]
@@ -94,10 +169,12 @@ impl Monitor {
                     SELECT
                         id,
                         image_id,
+                        clip_id,
                         label,
                         confidence,
                         monitor_id,
-                        created_at
+                        created_at,
+                        model_version
                     FROM entities
                     WHERE (
                         ($1::text IS NOT NULL AND monitor_id = $1)
@@ -118,7 +195,7 @@ impl Monitor {
                     let has_next_page = entities.len() > first;
                     let mut connection = Connection::new(after.unwrap_or(0) > 0, has_next_page);
 
-                    entities.truncate(first as usize);
+                    entities.truncate(first);
 
                     connection.edges.extend(
                         entities
@@ -137,10 +214,12 @@ impl Monitor {
                     SELECT
                         id,
                         image_id,
+                        clip_id,
                         label,
                         confidence,
                         monitor_id,
-                        created_at
+                        created_at,
+                        model_version
                     FROM entities
                     WHERE (
                         ($1::text IS NOT NULL AND monitor_id = $1)
@@ -0,0 +1,62 @@
+use std::collections::HashSet;
+
+use anyhow::Context as _;
+
+use crate::event::{Context, RecognitionResults, RecognizedEventHandler};
+
+/// Ask the stream extractor to export a video clip around the detections.
+///
+/// The monitors without recording ignore the triggers.
+#[derive(Clone)]
+pub struct ClipHandler {
+    client: async_nats::Client,
+}
+
+impl ClipHandler {
+    pub fn new(client: async_nats::Client) -> Self {
+        Self { client }
+    }
+}
+
+#[derive(serde::Serialize)]
+struct ClipTrigger<'a> {
+    frame_id: &'a str,
+    captured_at: chrono::DateTime<chrono::FixedOffset>,
+}
+
+#[async_trait::async_trait]
+impl RecognizedEventHandler for ClipHandler {
+    #[tracing::instrument(skip_all)]
+    async fn on_receive_recognition_result(
+        &self,
+        _: &Context,
+        result: &RecognitionResults,
+    ) -> anyhow::Result<()> {
+        // the results of a frame share a clip
+        let mut triggered_frames = HashSet::new();
+
+        for result in &result.results {
+            let Some(monitor_id) = &result.monitor_id else {
+                continue;
+            };
+            if !triggered_frames.insert(&result.frame_id) {
+                continue;
+            }
+
+            tracing::info!("Triggering a clip of frame {}", result.frame_id);
+
+            let trigger = ClipTrigger {
+                frame_id: &result.frame_id,
+                captured_at: result.created_at,
+            };
+            let payload = serde_json::to_vec(&trigger)?;
+
+            self.client
+                .publish(format!("clips.trigger.{monitor_id}"), payload.into())
+                .await
+                .context("Failed to publish the clip trigger")?;
+        }
+
+        Ok(())
+    }
+}
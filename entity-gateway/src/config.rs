@@ -5,12 +5,26 @@ use config::{Environment, File, FileFormat, builder::DefaultState};
 use dotenvy::vars;
 use opendal::services::S3Config;
 
+use crate::{jetstream::JetStreamConfig, status::MonitorStatusConfig};
+
 #[derive(serde::Deserialize)]
 pub struct GatewayConfig {
     pub database_url: String,
     pub nats_url: String,
     pub discord_webhook_url: String,
     pub s3: S3Config,
+    /// Consume the recognition results from JetStream instead of core NATS if set.
+    pub jetstream: Option<JetStreamConfig>,
+    /// How long to wait for the in-flight results to be handled on shutdown, in seconds.
+    #[serde(default = "default_shutdown_timeout_secs")]
+    pub shutdown_timeout_secs: u64,
+    /// When a monitor is considered offline.
+    #[serde(default)]
+    pub monitor_status: MonitorStatusConfig,
+}
+
+fn default_shutdown_timeout_secs() -> u64 {
+    30
 }
 
 pub fn parse_config() -> anyhow::Result<GatewayConfig> {
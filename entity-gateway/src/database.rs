@@ -1,6 +1,10 @@
+use anyhow::Context as _;
 use bigdecimal::FromPrimitive;
 
-use crate::event::{Context, RecognitionResults, RecognizedEventHandler};
+use crate::event::{
+    ClipMessage, Context, MonitorAnnouncement, MonitorStatusChange, RecognitionResults,
+    RecognizedEventHandler,
+};
 
 #[derive(Clone)]
 pub struct DatabaseHandler {
@@ -13,74 +17,161 @@ impl DatabaseHandler {
 
         Ok(Self { pool })
     }
+
+    /// Insert or update the monitor with its announced details.
+    #[tracing::instrument(skip_all, fields(monitor_id = %announcement.id))]
+    pub async fn upsert_monitor(&self, announcement: &MonitorAnnouncement) -> anyhow::Result<()> {
+        sqlx::query!(
+            r#"
+            INSERT INTO monitors (id, name, location, width, height, fps, codec, software_version, announced_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+            ON CONFLICT (id) DO UPDATE SET
+                name = EXCLUDED.name,
+                location = EXCLUDED.location,
+                width = EXCLUDED.width,
+                height = EXCLUDED.height,
+                fps = EXCLUDED.fps,
+                codec = EXCLUDED.codec,
+                software_version = EXCLUDED.software_version,
+                announced_at = EXCLUDED.announced_at
+            "#,
+            announcement.id,
+            announcement.name,
+            announcement.location,
+            announcement.width,
+            announcement.height,
+            announcement.fps,
+            announcement.codec,
+            announcement.version,
+            announcement.announced_at,
+        )
+        .execute(&self.pool)
+        .await
+        .context("Failed to upsert the monitor")?;
+
+        tracing::debug!("Updated monitor {}", announcement.id);
+
+        Ok(())
+    }
+
+    /// Attach the clip to the entities detected in its frames.
+    #[tracing::instrument(skip_all)]
+    pub async fn attach_clip(&self, clip: &ClipMessage) -> anyhow::Result<()> {
+        let attached = sqlx::query!(
+            r#"
+            UPDATE entities SET clip_id = $1
+            WHERE frame_id = ANY($2) AND monitor_id = $3
+            "#,
+            clip.clip_id,
+            &clip.frame_ids,
+            clip.monitor_id,
+        )
+        .execute(&self.pool)
+        .await
+        .context("Failed to attach the clip to the entities")?;
+
+        tracing::info!(
+            "Attached clip {} to {} entities",
+            clip.clip_id,
+            attached.rows_affected()
+        );
+
+        Ok(())
+    }
 }
 
 #[async_trait::async_trait]
 impl RecognizedEventHandler for DatabaseHandler {
     #[tracing::instrument(skip_all)]
-    async fn on_receive_recognition_result(&self, context: &Context, result: &RecognitionResults) {
+    async fn on_receive_recognition_result(
+        &self,
+        context: &Context,
+        result: &RecognitionResults,
+    ) -> anyhow::Result<()> {
         tracing::info!(
             "Received recognition result from the event bus and sending it to the database"
         );
 
         let storage = context.storage.clone();
 
-        for result in &result.results {
-            let image_key = match storage.put_recognition_result(&result).await {
-                Ok(key) => key,
-                Err(err) => {
-                    tracing::error!("Failed to put recognition result to storage: {:?}", err);
-                    continue;
-                }
-            };
+        for (index, result) in result.results.iter().enumerate() {
+            let image_key = storage
+                .put_recognition_result(result, index)
+                .await
+                .context("Failed to put recognition result to storage")?;
 
             let confidence = bigdecimal::BigDecimal::from_f32(result.confidence)
                 .map(|b| b.round(4))
                 .unwrap_or_else(|| bigdecimal::BigDecimal::from_f32(0.0).unwrap());
 
-            // check if there is such monitor_id in the database
-            let monitor_id = sqlx::query!(
+            // Register the monitor if it is not known yet, e.g. it has never been announced.
+            sqlx::query!(
                 r#"
-                SELECT id FROM monitors WHERE id = $1
+                INSERT INTO monitors (id)
+                VALUES ($1)
+                ON CONFLICT (id) DO NOTHING
                 "#,
-                result.monitor_id
+                result.monitor_id,
             )
-            .fetch_one(&self.pool)
-            .await;
-            match monitor_id {
-                Ok(_) => {}
-                Err(sqlx::Error::RowNotFound) => {
-                    sqlx::query!(
-                        r#"
-                        INSERT INTO monitors (id)
-                        VALUES ($1)
-                        "#,
-                        result.monitor_id,
-                    )
-                    .execute(&self.pool)
-                    .await
-                    .unwrap();
-                }
-                Err(err) => {
-                    tracing::error!("Failed to check if there is such monitor: {:?}", err);
-                    continue;
-                }
-            }
-
-            let _ = sqlx::query!(
+            .execute(&self.pool)
+            .await
+            .context("Failed to insert the monitor")?;
+
+            sqlx::query!(
                 r#"
-                INSERT INTO entities (image_id, monitor_id, confidence, label, created_at)
-                VALUES ($1, $2, $3, $4, $5)
+                INSERT INTO entities (image_id, frame_id, monitor_id, confidence, label, created_at, model_version)
+                VALUES ($1, $2, $3, $4, $5, $6, $7)
+                ON CONFLICT (image_id) DO NOTHING
                 "#,
                 image_key,
+                result.frame_id,
                 result.monitor_id,
                 confidence,
                 result.label,
                 result.created_at,
+                result.model_version,
             )
             .execute(&self.pool)
             .await
-            .unwrap();
+            .context("Failed to insert the entity")?;
         }
+
+        Ok(())
+    }
+
+    #[tracing::instrument(skip_all, fields(monitor_id = %change.monitor_id))]
+    async fn on_monitor_status_change(
+        &self,
+        _: &Context,
+        change: &MonitorStatusChange,
+    ) -> anyhow::Result<()> {
+        sqlx::query!(
+            r#"
+            INSERT INTO monitors (id)
+            VALUES ($1)
+            ON CONFLICT (id) DO NOTHING
+            "#,
+            change.monitor_id,
+        )
+        .execute(&self.pool)
+        .await
+        .context("Failed to insert the monitor")?;
+
+        sqlx::query!(
+            r#"
+            INSERT INTO monitor_status_history (monitor_id, online, pipeline_state, last_frame_at, created_at)
+            VALUES ($1, $2, $3, $4, $5)
+            "#,
+            change.monitor_id,
+            change.online,
+            change.pipeline_state,
+            change.last_frame_at,
+            change.changed_at,
+        )
+        .execute(&self.pool)
+        .await
+        .context("Failed to record the status of the monitor")?;
+
+        Ok(())
     }
 }
@@ -1,6 +1,8 @@
 use std::{collections::BTreeMap, sync::Arc};
 
-use crate::event::{Context, RecognitionResults, RecognizedEventHandler};
+use crate::event::{
+    Context, MonitorStatusChange, RecognitionResults, RecognizedEventHandler, TamperEvent,
+};
 
 #[derive(Clone)]
 pub struct DiscordHandler {
@@ -20,7 +22,11 @@ impl DiscordHandler {
 #[async_trait::async_trait]
 impl RecognizedEventHandler for DiscordHandler {
     #[tracing::instrument(skip_all)]
-    async fn on_receive_recognition_result(&self, _: &Context, result: &RecognitionResults) {
+    async fn on_receive_recognition_result(
+        &self,
+        _: &Context,
+        result: &RecognitionResults,
+    ) -> anyhow::Result<()> {
         tracing::info!("Received recognition result from the event bus and sending it to Discord");
 
         for result in &result.results {
@@ -49,9 +55,106 @@ impl RecognizedEventHandler for DiscordHandler {
                     tracing::info!("Successfully sent the message to Discord: {id:?}");
                 }
                 Err(e) => {
-                    tracing::error!("Failed to send the message to Discord: {e:?}");
+                    anyhow::bail!("Failed to send the message to Discord: {e:?}");
                 }
             }
         }
+
+        Ok(())
+    }
+
+    #[tracing::instrument(skip_all, fields(monitor_id = %event.monitor_id))]
+    async fn on_tamper_detected(&self, _: &Context, event: &TamperEvent) -> anyhow::Result<()> {
+        tracing::info!("Sending the tamper event to Discord");
+
+        let kind = match event.kind.as_str() {
+            "covered" => "鏡頭被遮蔽",
+            "blacked_out" => "畫面全黑",
+            "defocused" => "畫面失焦",
+            "moved" => "攝影機被移動",
+            "scene_changed" => "畫面突然改變",
+            kind => kind,
+        };
+
+        let message = discord_webhook2::message::Message::new(|message| {
+            message.embed(|embed| {
+                embed
+                    .title("⚠️ 攝影機遭到破壞 ⚠️")
+                    .description("請檢查攝影機。")
+                    .field(|field| field.name("攝影機").value(&event.monitor_id))
+                    .field(|field| field.name("發現時間").value(event.created_at.to_string()))
+                    .field(|field| field.name("破壞類型").value(kind))
+            })
+        });
+
+        let extension = event
+            .picture_type
+            .extensions_str()
+            .first()
+            .unwrap_or(&"jpg");
+        let mut files_entries = BTreeMap::new();
+        files_entries.insert(format!("picture.{extension}"), event.picture.to_vec());
+
+        let result = discord_webhook2::webhook::DiscordWebhook::send_with_files(
+            &self.client,
+            &message,
+            files_entries,
+        )
+        .await;
+
+        match result {
+            Ok(id) => {
+                tracing::info!("Successfully sent the message to Discord: {id:?}");
+            }
+            Err(e) => {
+                anyhow::bail!("Failed to send the message to Discord: {e:?}");
+            }
+        }
+
+        Ok(())
+    }
+
+    #[tracing::instrument(skip_all, fields(monitor_id = %change.monitor_id))]
+    async fn on_monitor_status_change(
+        &self,
+        _: &Context,
+        change: &MonitorStatusChange,
+    ) -> anyhow::Result<()> {
+        tracing::info!("Sending the status change of the monitor to Discord");
+
+        let (title, description) = if change.online {
+            ("✅ 攝影機恢復連線 ✅", "攝影機已恢復傳送畫面。")
+        } else {
+            (
+                "⚠️ 攝影機離線 ⚠️",
+                "攝影機已一段時間沒有傳送畫面，請檢查攝影機。",
+            )
+        };
+        let last_frame_at = change
+            .last_frame_at
+            .map(|at| at.to_string())
+            .unwrap_or_else(|| "無".to_string());
+
+        let message = discord_webhook2::message::Message::new(|message| {
+            message.embed(|embed| {
+                embed
+                    .title(title)
+                    .description(description)
+                    .field(|field| field.name("攝影機").value(&change.monitor_id))
+                    .field(|field| field.name("最後畫面時間").value(last_frame_at))
+                    .field(|field| field.name("串流狀態").value(&change.pipeline_state))
+            })
+        });
+
+        match self.client.send(&message).await {
+            Ok(id) => {
+                tracing::info!("Successfully sent the message to Discord: {id:?}");
+            }
+            Err(e) => {
+                anyhow::bail!("Failed to send the message to Discord: {e:?}");
+            }
+        }
+
+        Ok(())
     }
 }
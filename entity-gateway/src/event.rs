@@ -1,5 +1,6 @@
 use std::sync::Arc;
 
+use anyhow::Context as _;
 use async_nats::Message;
 use bytes::Bytes;
 use image::ImageFormat;
@@ -17,7 +18,33 @@ pub struct Context {
 
 #[async_trait::async_trait]
 pub trait RecognizedEventHandler: Sync + Send {
-    async fn on_receive_recognition_result(&self, context: &Context, result: &RecognitionResults);
+    /// Handle the recognition results.
+    ///
+    /// A handler returning an error is run again, alone, a few times. In the JetStream mode,
+    /// the results are redelivered only if every handler keeps failing, so it should be idempotent.
+    async fn on_receive_recognition_result(
+        &self,
+        context: &Context,
+        result: &RecognitionResults,
+    ) -> anyhow::Result<()>;
+
+    /// Handle a monitor being tampered with.
+    async fn on_tamper_detected(
+        &self,
+        _context: &Context,
+        _event: &TamperEvent,
+    ) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Handle a monitor going offline or recovering.
+    async fn on_monitor_status_change(
+        &self,
+        _context: &Context,
+        _change: &MonitorStatusChange,
+    ) -> anyhow::Result<()> {
+        Ok(())
+    }
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -31,6 +58,126 @@ pub struct RecognitionResult {
     pub picture: Bytes,
     pub picture_type: ImageFormat,
     pub created_at: chrono::DateTime<chrono::FixedOffset>,
+    /// The version of the model which recognized the entity, or `None` from the older workers.
+    #[serde(default)]
+    pub model_version: Option<String>,
+}
+
+/// A video clip around the detections, exported by the stream extractor.
+#[derive(Debug, Clone, Deserialize)]
+#[allow(unused)]
+pub struct ClipMessage {
+    pub monitor_id: String,
+    /// The key of the clip in the storage.
+    pub clip_id: String,
+    /// The frames of the detections covered by the clip.
+    pub frame_ids: Vec<String>,
+    pub start: chrono::DateTime<chrono::FixedOffset>,
+    pub end: chrono::DateTime<chrono::FixedOffset>,
+}
+
+impl TryFrom<Message> for ClipMessage {
+    type Error = anyhow::Error;
+
+    fn try_from(message: Message) -> Result<Self, Self::Error> {
+        Ok(serde_json::from_slice(&message.payload)?)
+    }
+}
+
+/// The announcement of a monitor by the stream extractor, received on `monitors.announce`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct MonitorAnnouncement {
+    pub id: String,
+    pub name: Option<String>,
+    pub location: Option<String>,
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    pub fps: Option<f64>,
+    pub codec: Option<String>,
+    /// The version of the stream extractor.
+    pub version: String,
+    pub announced_at: chrono::DateTime<chrono::FixedOffset>,
+}
+
+impl TryFrom<Message> for MonitorAnnouncement {
+    type Error = anyhow::Error;
+
+    fn try_from(message: Message) -> Result<Self, Self::Error> {
+        Ok(serde_json::from_slice(&message.payload)?)
+    }
+}
+
+/// The heartbeat of a monitor, received on `monitors.status.<monitor_id>`.
+#[derive(Debug, Clone, Deserialize)]
+#[allow(unused)]
+pub struct MonitorHeartbeat {
+    pub monitor_id: String,
+    /// The state of the pipeline: `starting`, `playing`, `reconnecting` or `finished`.
+    pub state: String,
+    pub state_since: chrono::DateTime<chrono::FixedOffset>,
+    pub last_frame_at: Option<chrono::DateTime<chrono::FixedOffset>>,
+    pub sent_at: chrono::DateTime<chrono::FixedOffset>,
+}
+
+impl TryFrom<Message> for MonitorHeartbeat {
+    type Error = anyhow::Error;
+
+    fn try_from(message: Message) -> Result<Self, Self::Error> {
+        Ok(serde_json::from_slice(&message.payload)?)
+    }
+}
+
+/// A monitor is tampered with (covered, defocused, moved or blacked out),
+/// received on `tamper.<monitor_id>`.
+#[derive(Debug, Clone)]
+pub struct TamperEvent {
+    pub frame_id: String,
+    pub monitor_id: String,
+    /// `covered`, `blacked_out`, `defocused`, `moved` or `scene_changed`.
+    pub kind: String,
+    pub picture: Bytes,
+    pub picture_type: ImageFormat,
+    pub created_at: chrono::DateTime<chrono::FixedOffset>,
+}
+
+impl TryFrom<Message> for TamperEvent {
+    type Error = anyhow::Error;
+
+    fn try_from(message: Message) -> Result<Self, Self::Error> {
+        let headers = message.headers.unwrap_or_default();
+        let header = |name: &str| {
+            headers
+                .get(name)
+                .map(|value| value.to_string())
+                .with_context(|| format!("missing {name} header"))
+        };
+
+        let content_type = header("Content-Type")?;
+        let picture_type = ImageFormat::from_mime_type(&content_type)
+            .with_context(|| format!("unsupported content type: {content_type}"))?;
+        let created_at = chrono::DateTime::parse_from_rfc3339(&header("Date")?)
+            .context("failed to parse Date header")?;
+
+        Ok(Self {
+            frame_id: header("Frame-Id")?,
+            monitor_id: header("Monitor-Id")?,
+            kind: header("Tamper-Kind")?,
+            picture: message.payload,
+            picture_type,
+            created_at,
+        })
+    }
+}
+
+/// A monitor went offline or recovered.
+#[derive(Debug, Clone)]
+pub struct MonitorStatusChange {
+    pub monitor_id: String,
+    pub online: bool,
+    /// The state of the pipeline in the last heartbeat.
+    pub pipeline_state: String,
+    pub last_frame_at: Option<chrono::DateTime<chrono::FixedOffset>>,
+    pub changed_at: chrono::DateTime<chrono::Utc>,
 }
 
 #[derive(Debug, Clone)]
@@ -0,0 +1,46 @@
+use std::time::Duration;
+
+use iot_common::jetstream::DurableConsumer;
+
+/// The configuration of the JetStream mode.
+///
+/// In this mode, the recognition results are consumed from a durable pull consumer,
+/// and acknowledged only after all the handlers succeed.
+#[derive(Clone, Debug, serde::Deserialize)]
+#[serde(default)]
+pub struct JetStreamConfig {
+    /// The name of the stream of the recognition results.
+    pub stream: String,
+
+    /// The name of the durable consumer.
+    pub durable_name: String,
+
+    /// How many times a result is delivered before giving up.
+    pub max_deliver: i64,
+
+    /// How long to wait for the acknowledgement before redelivering a result, in seconds.
+    pub ack_wait_secs: u64,
+}
+
+impl Default for JetStreamConfig {
+    fn default() -> Self {
+        Self {
+            stream: "RECOGNITION".to_string(),
+            durable_name: "entity-gateway".to_string(),
+            max_deliver: 5,
+            ack_wait_secs: 60,
+        }
+    }
+}
+
+impl JetStreamConfig {
+    /// The durable consumer of the recognition results.
+    pub fn consumer(&self) -> DurableConsumer<'_> {
+        DurableConsumer {
+            stream: &self.stream,
+            durable_name: &self.durable_name,
+            max_deliver: self.max_deliver,
+            ack_wait: Duration::from_secs(self.ack_wait_secs),
+        }
+    }
+}
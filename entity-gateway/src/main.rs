@@ -1,16 +1,30 @@
+pub(crate) mod clip;
 pub(crate) mod config;
 pub(crate) mod database;
 pub(crate) mod discord;
 pub(crate) mod event;
+pub(crate) mod jetstream;
+pub(crate) mod status;
 pub(crate) mod storage;
 
-use std::sync::Arc;
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
 use anyhow::Context as _;
 use config::GatewayConfig;
 use event::{Context, RecognitionResults, RecognizedEventHandler};
 use futures::StreamExt as _;
-use tokio_util::task::TaskTracker;
+use iot_common::shutdown::shutdown_signal;
+use jetstream::JetStreamConfig;
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
+
+/// How many times a handler is run on the recognition results before giving up.
+const HANDLER_ATTEMPTS: u32 = 3;
+
+/// How long to wait before running the failed handlers again, doubled on each attempt.
+const HANDLER_RETRY_INTERVAL: Duration = Duration::from_secs(1);
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
@@ -21,6 +35,9 @@ async fn main() -> anyhow::Result<()> {
         nats_url,
         discord_webhook_url,
         s3,
+        jetstream,
+        shutdown_timeout_secs,
+        monitor_status,
     } = config::parse_config()?;
 
     let nats_client = async_nats::connect(&nats_url)
@@ -35,24 +52,159 @@ async fn main() -> anyhow::Result<()> {
 
     let task_tracker = TaskTracker::new();
 
-    let mut recognition_subscriber = nats_client.subscribe("recognition").await?;
+    let mut recognition_subscriber = iot_common::jetstream::subscribe(
+        &nats_client,
+        "recognition",
+        jetstream.as_ref().map(JetStreamConfig::consumer),
+        None,
+    )
+    .await?;
+
+    let database_handler = database::DatabaseHandler::connect(&database_url).await?;
 
     let publishers: Vec<Arc<dyn RecognizedEventHandler>> = vec![
         {
             let discord_handler = discord::DiscordHandler::new(&discord_webhook_url)?;
             Arc::new(discord_handler) as Arc<dyn RecognizedEventHandler>
         },
+        Arc::new(database_handler.clone()) as Arc<dyn RecognizedEventHandler>,
         {
-            let database_handler = database::DatabaseHandler::connect(&database_url).await?;
-            Arc::new(database_handler) as Arc<dyn RecognizedEventHandler>
+            let clip_handler = clip::ClipHandler::new(nats_client.clone());
+            Arc::new(clip_handler) as Arc<dyn RecognizedEventHandler>
         },
     ];
 
-    while let Some(message) = recognition_subscriber.next().await {
+    // Attach the clips exported by the stream extractors to the entities until the shutdown.
+    let shutdown = CancellationToken::new();
+    spawn_subscriber(
+        &task_tracker,
+        shutdown.clone(),
+        nats_client.subscribe("clips").await?,
+        "clip",
+        {
+            let database_handler = database_handler.clone();
+            move |clip: event::ClipMessage| {
+                let database_handler = database_handler.clone();
+                async move {
+                    if let Err(err) = database_handler.attach_clip(&clip).await {
+                        tracing::error!("Failed to handle clip: {:?}", err);
+                    }
+                }
+            }
+        },
+    );
+
+    // Register the monitors announced by the stream extractors until the shutdown.
+    spawn_subscriber(
+        &task_tracker,
+        shutdown.clone(),
+        nats_client.subscribe("monitors.announce").await?,
+        "monitor announcement",
+        move |announcement: event::MonitorAnnouncement| {
+            let database_handler = database_handler.clone();
+            async move {
+                if let Err(err) = database_handler.upsert_monitor(&announcement).await {
+                    tracing::error!("Failed to handle monitor announcement: {:?}", err);
+                }
+            }
+        },
+    );
+
+    // Notify the monitors going offline or recovering from their heartbeats until the shutdown.
+    let watchdog = Arc::new(Mutex::new(status::MonitorWatchdog::new(&monitor_status)));
+    spawn_subscriber(
+        &task_tracker,
+        shutdown.clone(),
+        nats_client.subscribe("monitors.status.*").await?,
+        "monitor heartbeat",
+        {
+            let watchdog = watchdog.clone();
+            let publishers = publishers.clone();
+            let context = context.clone();
+            move |heartbeat: event::MonitorHeartbeat| {
+                let change = lock_watchdog(&watchdog).record(heartbeat);
+                let publishers = publishers.clone();
+                let context = context.clone();
+                async move {
+                    notify_status_changes(&publishers, &context, change.into_iter().collect())
+                        .await;
+                }
+            }
+        },
+    );
+    task_tracker.spawn({
+        let publishers = publishers.clone();
+        let context = context.clone();
+        let shutdown = shutdown.clone();
+        async move {
+            let mut ticker = tokio::time::interval(lock_watchdog(&watchdog).check_interval());
+
+            loop {
+                tokio::select! {
+                    _ = ticker.tick() => {}
+                    _ = shutdown.cancelled() => break,
+                }
+
+                let changes = lock_watchdog(&watchdog).check();
+                notify_status_changes(&publishers, &context, changes).await;
+            }
+        }
+    });
+
+    // Notify the monitors being tampered with until the shutdown.
+    spawn_subscriber(
+        &task_tracker,
+        shutdown.clone(),
+        nats_client.subscribe("tamper.*").await?,
+        "tamper event",
+        {
+            let publishers = publishers.clone();
+            let context = context.clone();
+            move |tamper_event: event::TamperEvent| {
+                let publishers = publishers.clone();
+                let context = context.clone();
+                async move {
+                    tracing::info!(
+                        "Monitor {} is tampered with: {} (frame {})",
+                        tamper_event.monitor_id,
+                        tamper_event.kind,
+                        tamper_event.frame_id
+                    );
+
+                    let results =
+                        futures::future::join_all(publishers.iter().map(|publisher| {
+                            publisher.on_tamper_detected(&context, &tamper_event)
+                        }))
+                        .await;
+
+                    for result in results {
+                        if let Err(err) = result {
+                            tracing::error!("Failed to handle tamper event: {:?}", err);
+                        }
+                    }
+                }
+            }
+        },
+    );
+
+    let signal = shutdown_signal();
+    tokio::pin!(signal);
+
+    loop {
+        // Stop receiving the results on shutdown. The unacknowledged ones are redelivered in JetStream mode.
+        let (message, acknowledger) = tokio::select! {
+            message = recognition_subscriber.next() => match message {
+                Some(message) => message,
+                None => break,
+            },
+            _ = &mut signal => break,
+        };
+
         let recognition_result = match event::RecognitionResults::try_from(message) {
             Ok(result) => result,
             Err(err) => {
                 tracing::error!("Failed to parse recognition result: {:?}", err);
+                acknowledger.term().await;
                 continue;
             }
         };
@@ -70,26 +222,158 @@ async fn main() -> anyhow::Result<()> {
 
         // if there is no result, skip the loop
         if recognition_result.results.is_empty() {
+            acknowledger.ack().await;
             continue;
         }
 
         let publishers = publishers.clone();
+        let context = context.clone();
 
-        for publisher in publishers {
-            let recognition_result = recognition_result.clone();
-            let context = context.clone();
+        task_tracker.spawn(async move {
+            let failed =
+                handle_recognition_results(&publishers, &context, &recognition_result).await;
 
-            task_tracker.spawn(async move {
-                publisher
-                    .on_receive_recognition_result(&context, &recognition_result)
-                    .await;
-            });
-        }
+            if failed == 0 {
+                acknowledger.ack().await;
+            } else if failed == publishers.len() {
+                // Nothing is handled yet, so the redelivery duplicates nothing.
+                acknowledger.nak().await;
+            } else {
+                tracing::error!(
+                    "Gave up handling recognition result with {failed} handlers; not redelivering it to the others."
+                );
+                acknowledger.ack().await;
+            }
+        });
     }
 
+    drop(recognition_subscriber);
+    shutdown.cancel();
+
+    // Drain the in-flight notifications and database writes.
     task_tracker.close();
+    if tokio::time::timeout(
+        Duration::from_secs(shutdown_timeout_secs),
+        task_tracker.wait(),
+    )
+    .await
+    .is_err()
+    {
+        tracing::warn!(
+            "Timed out waiting for {} tasks to finish; dropping them.",
+            task_tracker.len()
+        );
+    }
 
-    task_tracker.wait().await;
+    if let Err(err) = nats_client.flush().await {
+        tracing::error!("Failed to flush the NATS client: {:?}", err);
+    }
 
     Ok(())
 }
+
+/// Run the handlers on the recognition results, retrying only the failed ones.
+///
+/// Returns the number of the handlers which still fail after [`HANDLER_ATTEMPTS`].
+async fn handle_recognition_results(
+    publishers: &[Arc<dyn RecognizedEventHandler>],
+    context: &Context,
+    recognition_result: &RecognitionResults,
+) -> usize {
+    let mut pending = publishers.iter().collect::<Vec<_>>();
+    let mut retry_interval = HANDLER_RETRY_INTERVAL;
+
+    for attempt in 1..=HANDLER_ATTEMPTS {
+        let results =
+            futures::future::join_all(pending.iter().map(|publisher| {
+                publisher.on_receive_recognition_result(context, recognition_result)
+            }))
+            .await;
+
+        pending = pending
+            .into_iter()
+            .zip(results)
+            .filter_map(|(publisher, result)| {
+                let err = result.err()?;
+                tracing::error!(
+                    "Failed to handle recognition result (attempt {attempt}/{HANDLER_ATTEMPTS}): {:?}",
+                    err
+                );
+                Some(publisher)
+            })
+            .collect();
+        if pending.is_empty() || attempt == HANDLER_ATTEMPTS {
+            break;
+        }
+
+        tokio::time::sleep(retry_interval).await;
+        retry_interval *= 2;
+    }
+
+    pending.len()
+}
+
+/// Handle the messages of the subscriber one at a time until the shutdown,
+/// skipping the ones which fail to parse as `T`.
+fn spawn_subscriber<T, F, Fut>(
+    tracker: &TaskTracker,
+    shutdown: CancellationToken,
+    mut subscriber: async_nats::Subscriber,
+    name: &'static str,
+    mut handler: F,
+) where
+    T: TryFrom<async_nats::Message, Error = anyhow::Error> + Send,
+    F: FnMut(T) -> Fut + Send + 'static,
+    Fut: Future<Output = ()> + Send,
+{
+    tracker.spawn(async move {
+        loop {
+            let message = tokio::select! {
+                message = subscriber.next() => message,
+                _ = shutdown.cancelled() => None,
+            };
+            let Some(message) = message else {
+                break;
+            };
+
+            match T::try_from(message) {
+                Ok(value) => handler(value).await,
+                Err(err) => tracing::error!("Failed to parse {name}: {:?}", err),
+            }
+        }
+    });
+}
+
+fn lock_watchdog(
+    watchdog: &Mutex<status::MonitorWatchdog>,
+) -> std::sync::MutexGuard<'_, status::MonitorWatchdog> {
+    watchdog.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Notify the handlers of the monitors going offline or recovering.
+async fn notify_status_changes(
+    publishers: &[Arc<dyn RecognizedEventHandler>],
+    context: &Context,
+    changes: Vec<event::MonitorStatusChange>,
+) {
+    for change in changes {
+        tracing::info!(
+            "Monitor {} is {}",
+            change.monitor_id,
+            if change.online { "online" } else { "offline" }
+        );
+
+        let results = futures::future::join_all(
+            publishers
+                .iter()
+                .map(|publisher| publisher.on_monitor_status_change(context, &change)),
+        )
+        .await;
+
+        for result in results {
+            if let Err(err) = result {
+                tracing::error!("Failed to handle monitor status change: {:?}", err);
+            }
+        }
+    }
+}
@@ -0,0 +1,252 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use crate::event::{MonitorHeartbeat, MonitorStatusChange};
+
+#[derive(Clone, Debug, serde::Deserialize)]
+#[serde(default)]
+pub struct MonitorStatusConfig {
+    /// How long a monitor can go without a heartbeat or a decoded frame before it is offline, in seconds.
+    pub offline_after_secs: u64,
+}
+
+impl Default for MonitorStatusConfig {
+    fn default() -> Self {
+        Self {
+            offline_after_secs: 60,
+        }
+    }
+}
+
+/// Track the heartbeats of the monitors, and detect the ones going offline or recovering.
+pub struct MonitorWatchdog {
+    offline_after: Duration,
+    monitors: HashMap<String, MonitorHealth>,
+}
+
+struct MonitorHealth {
+    heartbeat: MonitorHeartbeat,
+    received_at: Instant,
+    /// When the first heartbeat was sent, for the monitors with no decoded frame yet.
+    first_sent_at: chrono::DateTime<chrono::FixedOffset>,
+    online: bool,
+}
+
+impl MonitorHealth {
+    fn is_alive(&self, offline_after: Duration) -> bool {
+        if self.received_at.elapsed() > offline_after {
+            return false;
+        }
+
+        // a file played to the end is not offline
+        if self.heartbeat.state == "finished" {
+            return true;
+        }
+
+        // Both timestamps are taken from the clock of the extractor.
+        let last_frame_at = self.heartbeat.last_frame_at.unwrap_or(self.first_sent_at);
+        let frame_age = self.heartbeat.sent_at - last_frame_at;
+        frame_age.to_std().unwrap_or_default() <= offline_after
+    }
+
+    /// Update the status of the monitor, returning the change if any.
+    fn update(&mut self, monitor_id: &str, offline_after: Duration) -> Option<MonitorStatusChange> {
+        let online = self.is_alive(offline_after);
+        if online == self.online {
+            return None;
+        }
+        self.online = online;
+
+        Some(MonitorStatusChange {
+            monitor_id: monitor_id.to_string(),
+            online,
+            pipeline_state: self.heartbeat.state.clone(),
+            last_frame_at: self.heartbeat.last_frame_at,
+            changed_at: chrono::Utc::now(),
+        })
+    }
+}
+
+impl MonitorWatchdog {
+    pub fn new(config: &MonitorStatusConfig) -> Self {
+        Self {
+            offline_after: Duration::from_secs(config.offline_after_secs),
+            monitors: HashMap::new(),
+        }
+    }
+
+    /// How often the monitors should be checked.
+    pub fn check_interval(&self) -> Duration {
+        (self.offline_after / 4).max(Duration::from_secs(1))
+    }
+
+    /// Record a heartbeat, returning the change of the monitor if any.
+    ///
+    /// A monitor is assumed to be online until it is seen otherwise.
+    pub fn record(&mut self, heartbeat: MonitorHeartbeat) -> Option<MonitorStatusChange> {
+        let monitor_id = heartbeat.monitor_id.clone();
+        let health = self
+            .monitors
+            .entry(monitor_id.clone())
+            .and_modify(|health| {
+                health.heartbeat = heartbeat.clone();
+                health.received_at = Instant::now();
+            })
+            .or_insert_with(|| MonitorHealth {
+                first_sent_at: heartbeat.sent_at,
+                heartbeat,
+                received_at: Instant::now(),
+                online: true,
+            });
+
+        health.update(&monitor_id, self.offline_after)
+    }
+
+    /// Check the silence of all the monitors, returning the changes.
+    pub fn check(&mut self) -> Vec<MonitorStatusChange> {
+        self.monitors
+            .iter_mut()
+            .filter_map(|(monitor_id, health)| health.update(monitor_id, self.offline_after))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn watchdog() -> MonitorWatchdog {
+        MonitorWatchdog::new(&MonitorStatusConfig {
+            offline_after_secs: 60,
+        })
+    }
+
+    /// A heartbeat sent `sent_after` seconds after the epoch of the test, with the last frame
+    /// decoded `frame_age` seconds before it if any.
+    fn heartbeat(state: &str, sent_after: i64, frame_age: Option<i64>) -> MonitorHeartbeat {
+        let epoch = chrono::DateTime::parse_from_rfc3339("2026-10-18T08:00:00+00:00").unwrap();
+        let sent_at = epoch + chrono::Duration::seconds(sent_after);
+
+        MonitorHeartbeat {
+            monitor_id: "front-door".to_string(),
+            state: state.to_string(),
+            state_since: epoch,
+            last_frame_at: frame_age
+                .map(|frame_age| sent_at - chrono::Duration::seconds(frame_age)),
+            sent_at,
+        }
+    }
+
+    /// Pretend the last heartbeat of the monitor was received `secs` seconds ago.
+    fn silence(watchdog: &mut MonitorWatchdog, secs: u64) {
+        let health = watchdog.monitors.get_mut("front-door").unwrap();
+        health.received_at = Instant::now()
+            .checked_sub(Duration::from_secs(secs))
+            .unwrap();
+    }
+
+    #[test]
+    fn a_new_monitor_is_online() {
+        let mut watchdog = watchdog();
+
+        assert!(watchdog.record(heartbeat("playing", 0, Some(1))).is_none());
+        assert!(watchdog.record(heartbeat("starting", 10, None)).is_none());
+        assert!(watchdog.check().is_empty());
+    }
+
+    #[test]
+    fn goes_offline_and_recovers_with_the_frames() {
+        let mut watchdog = watchdog();
+        watchdog.record(heartbeat("playing", 0, Some(1)));
+
+        let change = watchdog
+            .record(heartbeat("playing", 120, Some(90)))
+            .unwrap();
+        assert!(!change.online);
+        assert_eq!(change.monitor_id, "front-door");
+        assert_eq!(change.pipeline_state, "playing");
+
+        // reported once
+        assert!(
+            watchdog
+                .record(heartbeat("reconnecting", 130, Some(100)))
+                .is_none()
+        );
+        assert!(watchdog.check().is_empty());
+
+        let change = watchdog.record(heartbeat("playing", 140, Some(0))).unwrap();
+        assert!(change.online);
+    }
+
+    #[test]
+    fn goes_offline_without_any_frame() {
+        let mut watchdog = watchdog();
+        assert!(watchdog.record(heartbeat("starting", 0, None)).is_none());
+        assert!(watchdog.record(heartbeat("starting", 30, None)).is_none());
+
+        let change = watchdog
+            .record(heartbeat("reconnecting", 90, None))
+            .unwrap();
+        assert!(!change.online);
+        assert_eq!(change.last_frame_at, None);
+    }
+
+    #[test]
+    fn goes_offline_and_recovers_with_the_heartbeats() {
+        let mut watchdog = watchdog();
+        watchdog.record(heartbeat("playing", 0, Some(1)));
+
+        silence(&mut watchdog, 30);
+        assert!(watchdog.check().is_empty());
+
+        silence(&mut watchdog, 90);
+        let changes = watchdog.check();
+        assert_eq!(changes.len(), 1);
+        assert!(!changes[0].online);
+        assert!(watchdog.check().is_empty());
+
+        let change = watchdog.record(heartbeat("playing", 90, Some(1))).unwrap();
+        assert!(change.online);
+    }
+
+    #[test]
+    fn a_finished_file_is_not_offline() {
+        let mut watchdog = watchdog();
+        watchdog.record(heartbeat("playing", 0, Some(1)));
+
+        // no frame is decoded once the file is played to the end
+        assert!(
+            watchdog
+                .record(heartbeat("finished", 600, Some(590)))
+                .is_none()
+        );
+        assert!(watchdog.check().is_empty());
+
+        // but the extractor itself can still go away
+        silence(&mut watchdog, 90);
+        let changes = watchdog.check();
+        assert_eq!(changes.len(), 1);
+        assert!(!changes[0].online);
+        assert_eq!(changes[0].pipeline_state, "finished");
+    }
+
+    #[test]
+    fn a_finished_file_recovers() {
+        let mut watchdog = watchdog();
+        watchdog.record(heartbeat("playing", 0, Some(1)));
+        assert!(
+            !watchdog
+                .record(heartbeat("playing", 120, Some(90)))
+                .unwrap()
+                .online
+        );
+
+        let change = watchdog
+            .record(heartbeat("finished", 130, Some(100)))
+            .unwrap();
+        assert!(change.online);
+        assert_eq!(change.pipeline_state, "finished");
+    }
+}
@@ -27,12 +27,17 @@ impl Storage {
 impl Storage {
     /// Put the image in the recognition result to the storage.
     ///
-    /// Returning the key of the image.
+    /// Returning the key of the image. The key is derived from the frame and the index of
+    /// the result in it, so putting the same result again overwrites the same image.
     pub async fn put_recognition_result(
         &self,
         result: &RecognitionResult,
+        index: usize,
     ) -> anyhow::Result<String> {
-        let image_id = uuid::Uuid::new_v4();
+        let image_id = uuid::Uuid::new_v5(
+            &uuid::Uuid::NAMESPACE_OID,
+            format!("{}/{index}", result.frame_id).as_bytes(),
+        );
         let image_key = format!("{}.{}", image_id, result.picture_type.extensions_str()[0]);
 
         self.operator
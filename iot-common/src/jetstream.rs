@@ -0,0 +1,236 @@
+use std::time::Duration;
+
+use anyhow::Context;
+use async_nats::{
+    HeaderMap, Message,
+    jetstream::{
+        self, AckKind, ErrorCode,
+        consumer::{AckPolicy, pull},
+        context::GetStreamErrorKind,
+        message::Acker,
+        stream::RetentionPolicy,
+    },
+};
+use bytes::Bytes;
+use futures::{StreamExt as _, stream::BoxStream};
+
+/// How long to wait before looking for a stream not created yet again.
+const STREAM_RETRY_INTERVAL: Duration = Duration::from_secs(5);
+
+/// The JetStream stream created by the publisher of a subject.
+#[derive(Clone, Copy, Debug)]
+pub struct PublishedStream<'a> {
+    /// The name of the stream.
+    pub name: &'a str,
+
+    /// The retention policy of the stream.
+    pub retention: RetentionPolicy,
+
+    /// The maximum age of the messages in the stream.
+    pub max_age: Duration,
+
+    /// The maximum size of the stream, in bytes. Unlimited if not set.
+    pub max_bytes: Option<i64>,
+}
+
+/// The durable pull consumer to receive the messages of a subject through JetStream.
+#[derive(Clone, Copy, Debug)]
+pub struct DurableConsumer<'a> {
+    /// The name of the stream of the subject.
+    ///
+    /// The stream is created by the publisher of the subject, with its retention and limits,
+    /// so the consumer waits for it instead of creating it.
+    pub stream: &'a str,
+
+    /// The name of the durable consumer, shared by the replicas.
+    pub durable_name: &'a str,
+
+    /// How many times a message is delivered before giving up.
+    pub max_deliver: i64,
+
+    /// How long to wait for the acknowledgement before redelivering a message.
+    pub ack_wait: Duration,
+}
+
+/// Acknowledge a message received from JetStream.
+///
+/// It does nothing for the messages received from core NATS.
+pub struct Acknowledger(Option<Acker>);
+
+impl Acknowledger {
+    /// The message is processed successfully.
+    pub async fn ack(&self) {
+        self.ack_with(AckKind::Ack).await;
+    }
+
+    /// The message failed to be processed, and should be redelivered.
+    pub async fn nak(&self) {
+        self.ack_with(AckKind::Nak(None)).await;
+    }
+
+    /// The message can never be processed, and should not be redelivered.
+    pub async fn term(&self) {
+        self.ack_with(AckKind::Term).await;
+    }
+
+    async fn ack_with(&self, kind: AckKind) {
+        if let Some(acker) = &self.0
+            && let Err(e) = acker.ack_with(kind).await
+        {
+            tracing::warn!("Failed to acknowledge the message: {:?}", e);
+        }
+    }
+}
+
+/// Subscribe to the subject, through a durable pull consumer if it is set.
+///
+/// With core NATS, the subscribers in the same `queue_group` share the messages,
+/// and the subscribers without a queue group receive all of them.
+pub async fn subscribe(
+    client: &async_nats::Client,
+    subject: &str,
+    consumer: Option<DurableConsumer<'_>>,
+    queue_group: Option<&str>,
+) -> anyhow::Result<BoxStream<'static, (Message, Acknowledger)>> {
+    let Some(consumer) = consumer else {
+        let subscriber = match queue_group {
+            Some(queue_group) => {
+                client
+                    .queue_subscribe(subject.to_string(), queue_group.to_string())
+                    .await?
+            }
+            None => client.subscribe(subject.to_string()).await?,
+        };
+        return Ok(subscriber
+            .map(|message| (message, Acknowledger(None)))
+            .boxed());
+    };
+
+    let context = jetstream::new(client.clone());
+    let stream = loop {
+        match context.get_stream(consumer.stream).await {
+            Ok(stream) => break stream,
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    GetStreamErrorKind::JetStream(e) if e.error_code() == ErrorCode::STREAM_NOT_FOUND
+                ) =>
+            {
+                tracing::warn!(
+                    "The JetStream stream {} is not created by the publisher of {subject} yet; waiting for it.",
+                    consumer.stream
+                );
+                tokio::time::sleep(STREAM_RETRY_INTERVAL).await;
+            }
+            Err(e) => {
+                return Err(e).with_context(|| {
+                    format!("Failed to get JetStream stream {}", consumer.stream)
+                });
+            }
+        }
+    };
+
+    let durable = stream
+        .get_or_create_consumer(
+            consumer.durable_name,
+            pull::Config {
+                durable_name: Some(consumer.durable_name.to_string()),
+                ack_policy: AckPolicy::Explicit,
+                ack_wait: consumer.ack_wait,
+                max_deliver: consumer.max_deliver,
+                filter_subject: subject.to_string(),
+                ..Default::default()
+            },
+        )
+        .await
+        .with_context(|| {
+            format!(
+                "Failed to create JetStream consumer {}",
+                consumer.durable_name
+            )
+        })?;
+
+    let messages = durable
+        .messages()
+        .await
+        .context("Failed to pull messages from JetStream")?;
+
+    Ok(messages
+        .filter_map(|message| async move {
+            match message {
+                Ok(message) => {
+                    let (message, acker) = message.split();
+                    Some((message, Acknowledger(Some(acker))))
+                }
+                Err(e) => {
+                    tracing::warn!("Failed to receive a message from JetStream: {:?}", e);
+                    None
+                }
+            }
+        })
+        .boxed())
+}
+
+/// Publish the messages through core NATS or JetStream.
+///
+/// The [`Clone`] operation is cheap.
+#[derive(Clone)]
+pub enum Publisher {
+    Core(async_nats::Client),
+    JetStream(jetstream::Context),
+}
+
+impl Publisher {
+    /// Create a publisher, and the JetStream stream of `subject` if it is set.
+    pub async fn new(
+        client: async_nats::Client,
+        subject: &str,
+        stream: Option<PublishedStream<'_>>,
+    ) -> anyhow::Result<Self> {
+        let Some(stream) = stream else {
+            return Ok(Self::Core(client));
+        };
+
+        let context = jetstream::new(client);
+        context
+            .get_or_create_stream(jetstream::stream::Config {
+                name: stream.name.to_string(),
+                subjects: vec![subject.to_string()],
+                retention: stream.retention,
+                max_age: stream.max_age,
+                max_bytes: stream.max_bytes.unwrap_or(-1),
+                ..Default::default()
+            })
+            .await
+            .with_context(|| format!("Failed to get or create JetStream stream {}", stream.name))?;
+
+        Ok(Self::JetStream(context))
+    }
+
+    /// Publish the message.
+    ///
+    /// In the JetStream mode, it returns after the server acknowledges the message.
+    pub async fn publish(
+        &self,
+        subject: &'static str,
+        headers: HeaderMap,
+        payload: Bytes,
+    ) -> anyhow::Result<()> {
+        match self {
+            Self::Core(client) => client
+                .publish_with_headers(subject, headers, payload)
+                .await
+                .context("Failed to publish the message to NATS")?,
+            Self::JetStream(context) => {
+                context
+                    .publish_with_headers(subject, headers, payload)
+                    .await
+                    .context("Failed to publish the message to JetStream")?
+                    .await
+                    .context("JetStream did not acknowledge the message")?;
+            }
+        }
+
+        Ok(())
+    }
+}
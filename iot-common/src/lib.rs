@@ -0,0 +1,5 @@
+//! The helpers shared by the services of the IoT system.
+
+#[cfg(feature = "jetstream")]
+pub mod jetstream;
+pub mod shutdown;
@@ -0,0 +1,5 @@
+-- Add down migration script here
+
+DROP INDEX IF EXISTS idx_entities_frame_id;
+ALTER TABLE entities DROP COLUMN clip_id;
+ALTER TABLE entities DROP COLUMN frame_id;
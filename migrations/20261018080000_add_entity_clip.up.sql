@@ -0,0 +1,6 @@
+-- Add up migration script here
+
+ALTER TABLE entities ADD COLUMN frame_id VARCHAR(255);
+ALTER TABLE entities ADD COLUMN clip_id VARCHAR(255);
+
+CREATE INDEX idx_entities_frame_id ON entities (frame_id);
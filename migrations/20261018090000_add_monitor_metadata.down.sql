@@ -0,0 +1,10 @@
+-- Add down migration script here
+
+ALTER TABLE monitors DROP COLUMN announced_at;
+ALTER TABLE monitors DROP COLUMN software_version;
+ALTER TABLE monitors DROP COLUMN codec;
+ALTER TABLE monitors DROP COLUMN fps;
+ALTER TABLE monitors DROP COLUMN height;
+ALTER TABLE monitors DROP COLUMN width;
+ALTER TABLE monitors DROP COLUMN location;
+ALTER TABLE monitors DROP COLUMN name;
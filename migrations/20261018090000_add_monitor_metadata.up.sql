@@ -0,0 +1,10 @@
+-- Add up migration script here
+
+ALTER TABLE monitors ADD COLUMN name VARCHAR(255);
+ALTER TABLE monitors ADD COLUMN location VARCHAR(255);
+ALTER TABLE monitors ADD COLUMN width INTEGER;
+ALTER TABLE monitors ADD COLUMN height INTEGER;
+ALTER TABLE monitors ADD COLUMN fps DOUBLE PRECISION;
+ALTER TABLE monitors ADD COLUMN codec VARCHAR(255);
+ALTER TABLE monitors ADD COLUMN software_version VARCHAR(255);
+ALTER TABLE monitors ADD COLUMN announced_at TIMESTAMPTZ;
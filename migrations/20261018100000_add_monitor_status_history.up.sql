@@ -0,0 +1,14 @@
+-- Add up migration script here
+
+CREATE TABLE monitor_status_history (
+    id SERIAL PRIMARY KEY,
+    monitor_id VARCHAR(255) NOT NULL REFERENCES monitors (id),
+    online BOOLEAN NOT NULL,
+    pipeline_state VARCHAR(255) NOT NULL,
+    last_frame_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_monitor_status_history_monitor_id ON monitor_status_history (
+    monitor_id, created_at
+);
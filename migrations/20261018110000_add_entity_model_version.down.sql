@@ -0,0 +1,3 @@
+-- Add down migration script here
+
+ALTER TABLE entities DROP COLUMN model_version;
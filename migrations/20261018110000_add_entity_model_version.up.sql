@@ -0,0 +1,3 @@
+-- Add up migration script here
+
+ALTER TABLE entities ADD COLUMN model_version VARCHAR(255);
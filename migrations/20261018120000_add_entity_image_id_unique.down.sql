@@ -0,0 +1,3 @@
+-- Add down migration script here
+
+ALTER TABLE entities DROP CONSTRAINT entities_image_id_key;
@@ -0,0 +1,4 @@
+-- Add up migration script here
+
+-- The entities are inserted once per detection, even if the recognition results are redelivered.
+ALTER TABLE entities ADD CONSTRAINT entities_image_id_key UNIQUE (image_id);
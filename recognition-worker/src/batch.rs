@@ -0,0 +1,136 @@
+use std::{sync::Arc, time::Duration};
+
+use anyhow::Context;
+use tokio::sync::oneshot;
+
+use crate::{
+    metrics::Metrics,
+    queue::{FrameQueue, QueuedFrame},
+    recognizer::{RecognitionPayload, RecognitionResult, RecognitionWorker},
+};
+
+/// The configuration of the batched inference.
+///
+/// The frames from all the monitors are collected into a batch, and recognized
+/// with a single inference by one of the inference threads.
+#[derive(Clone, Debug, serde::Deserialize)]
+#[serde(default)]
+pub struct BatchConfig {
+    /// The maximum number of frames in a batch. `1` disables batching.
+    pub max_size: usize,
+
+    /// How long the first frame of a batch waits for the others, in milliseconds.
+    pub max_wait_ms: u64,
+
+    /// The number of inference threads, each recognizing a batch at a time.
+    pub threads: usize,
+}
+
+impl Default for BatchConfig {
+    fn default() -> Self {
+        Self {
+            max_size: 8,
+            max_wait_ms: 20,
+            threads: 1,
+        }
+    }
+}
+
+impl BatchConfig {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.max_size == 0 {
+            anyhow::bail!("batch.max_size should be greater than 0");
+        }
+        if self.threads == 0 {
+            anyhow::bail!("batch.threads should be greater than 0");
+        }
+
+        Ok(())
+    }
+}
+
+/// Collect the queued frames into batches, and recognize them on a fixed pool of threads.
+///
+/// The [`Clone`] operation is cheap.
+#[derive(Clone)]
+pub struct BatchScheduler {
+    queue: Arc<FrameQueue>,
+}
+
+impl BatchScheduler {
+    /// Start the inference threads.
+    pub fn start(
+        config: BatchConfig,
+        queue: Arc<FrameQueue>,
+        worker: RecognitionWorker,
+        metrics: Arc<Metrics>,
+    ) -> anyhow::Result<Self> {
+        for index in 0..config.threads {
+            let queue = queue.clone();
+            let worker = worker.clone();
+            let metrics = metrics.clone();
+            let config = config.clone();
+
+            std::thread::Builder::new()
+                .name(format!("inference-{index}"))
+                .spawn(move || run_batches(&queue, &worker, &metrics, &config))
+                .context("Failed to create the inference thread")?;
+        }
+
+        Ok(Self { queue })
+    }
+
+    /// Queue the frame to be recognized along with the other frames.
+    ///
+    /// The frame is queued immediately, and the returned future resolves to its results,
+    /// or to a [`Shed`](crate::queue::Shed) error if it is shed.
+    pub fn submit(
+        &self,
+        payload: RecognitionPayload,
+    ) -> impl Future<Output = anyhow::Result<Vec<RecognitionResult>>> + Send + 'static {
+        let (reply, results) = oneshot::channel();
+        self.queue.push(QueuedFrame::new(payload, reply));
+
+        async move {
+            results
+                .await
+                .context("The inference pool dropped the frame")?
+        }
+    }
+
+    /// Stop the inference threads once the queued frames are recognized.
+    pub fn close(&self) {
+        self.queue.close();
+    }
+}
+
+fn run_batches(
+    queue: &FrameQueue,
+    worker: &RecognitionWorker,
+    metrics: &Metrics,
+    config: &BatchConfig,
+) {
+    let max_wait = Duration::from_millis(config.max_wait_ms);
+
+    while let Some(frames) = queue.next_batch(config.max_size, max_wait) {
+        if frames.is_empty() {
+            continue;
+        }
+
+        tracing::debug!("Recognizing a batch of {} frames.", frames.len());
+        metrics.batch_size.observe(frames.len() as f64);
+
+        let (payloads, replies): (Vec<_>, Vec<_>) = frames
+            .into_iter()
+            .map(|frame| (frame.payload, frame.reply))
+            .unzip();
+
+        let results = worker.recognize_batch(payloads);
+        for (reply, results) in replies.into_iter().zip(results) {
+            // the frame may be dropped by then
+            let _ = reply.send(results);
+        }
+    }
+
+    tracing::debug!("The inference thread is stopped.");
+}
@@ -1,11 +1,92 @@
+use std::collections::HashMap;
+
 use anyhow::Context;
+use config::{Environment, File, FileFormat, builder::DefaultState};
+use dotenvy::vars;
+
+use crate::{
+    batch::BatchConfig, jetstream::JetStreamConfig, model::ModelConfig, partition::PartitionConfig,
+    queue::QueueConfig, server::ServerConfig,
+};
 
+#[derive(serde::Deserialize)]
 pub struct RecognitionConfig {
     pub nats_url: String,
+    /// Consume the frames and publish the results through JetStream instead of core NATS if set.
+    pub jetstream: Option<JetStreamConfig>,
+    /// The queue group of the workers sharing the frames with core NATS.
+    #[serde(default = "default_queue_group")]
+    pub queue_group: String,
+    /// Stick the frames of each monitor to one worker if set, instead of the queue group.
+    pub partition: Option<PartitionConfig>,
+    /// How long to wait for the in-flight frames on shutdown, in seconds.
+    #[serde(default = "default_shutdown_timeout_secs")]
+    pub shutdown_timeout_secs: u64,
+    /// The YOLO model to recognize the frames with.
+    #[serde(default)]
+    pub model: ModelConfig,
+    /// How the frames are batched for the inference.
+    #[serde(default)]
+    pub batch: BatchConfig,
+    /// The queue of the frames waiting for the inference.
+    #[serde(default)]
+    pub queue: QueueConfig,
+    /// The HTTP server of the metrics.
+    #[serde(default)]
+    pub server: ServerConfig,
+}
+
+fn default_queue_group() -> String {
+    "recognition-worker".to_string()
+}
+
+fn default_shutdown_timeout_secs() -> u64 {
+    30
 }
 
 pub fn parse_config() -> anyhow::Result<RecognitionConfig> {
-    let nats_url = std::env::var("RECOGNITION_NATS_URL").context("RECOGNITION_NATS_URL environment variable is not set. NATS is required for the recognition worker to receive the sampled frame and send the recognized images.")?;
+    let dotenv_variables = HashMap::from_iter(vars());
+
+    let config = config::ConfigBuilder::<DefaultState>::default()
+        .add_source(
+            Environment::default()
+                .prefix("IOT")
+                .prefix_separator("_")
+                .keep_prefix(false)
+                .separator("__"),
+        )
+        .add_source(
+            Environment::default()
+                .source(Some(dotenv_variables))
+                .separator("__"),
+        )
+        .add_source(File::new("config.toml", FileFormat::Toml).required(false))
+        .build()
+        .context("Failed to build configuration")?;
+
+    let deserialized_config: RecognitionConfig = config
+        .try_deserialize()
+        .context("Failed to deserialize configuration")?;
+
+    if let Some(partition) = &deserialized_config.partition {
+        if deserialized_config.jetstream.is_some() {
+            // The durable consumer shares the frames among the workers already.
+            anyhow::bail!("partition is not supported in the JetStream mode");
+        }
+        partition.validate().context("Invalid partition")?;
+    }
+    deserialized_config
+        .model
+        .validate()
+        .context("Invalid model")?;
+    deserialized_config
+        .batch
+        .validate()
+        .context("Invalid batch")?;
+    deserialized_config
+        .queue
+        .validate()
+        .context("Invalid queue")?;
 
-    Ok(RecognitionConfig { nats_url })
+    Ok(deserialized_config)
 }
@@ -0,0 +1,76 @@
+use std::time::Duration;
+
+use async_nats::jetstream::stream::RetentionPolicy;
+use iot_common::jetstream::{DurableConsumer, PublishedStream};
+
+/// The configuration of the JetStream mode.
+///
+/// In this mode, the frames are consumed from a durable pull consumer and acknowledged
+/// only after the results are published, and the results are published to a JetStream stream.
+#[derive(Clone, Debug, serde::Deserialize)]
+#[serde(default)]
+pub struct JetStreamConfig {
+    /// The name of the stream of the frames.
+    pub frames_stream: String,
+
+    /// The name of the stream of the recognition results.
+    pub recognition_stream: String,
+
+    /// The retention policy of the stream of the recognition results:
+    /// `limits`, `interest` or `workqueue`.
+    pub retention: RetentionPolicy,
+
+    /// The maximum age of the recognition results in the stream, in seconds.
+    pub max_age_secs: u64,
+
+    /// The maximum size of the stream of the recognition results, in bytes. Unlimited if not set.
+    pub max_bytes: Option<i64>,
+
+    /// The name of the durable consumer of the frames.
+    ///
+    /// The workers with the same durable name share the frames.
+    pub durable_name: String,
+
+    /// How many times a frame is delivered before giving up.
+    pub max_deliver: i64,
+
+    /// How long to wait for the acknowledgement before redelivering a frame, in seconds.
+    pub ack_wait_secs: u64,
+}
+
+impl Default for JetStreamConfig {
+    fn default() -> Self {
+        Self {
+            frames_stream: "FRAMES".to_string(),
+            recognition_stream: "RECOGNITION".to_string(),
+            retention: RetentionPolicy::WorkQueue,
+            max_age_secs: 3600,
+            max_bytes: None,
+            durable_name: "recognition-worker".to_string(),
+            max_deliver: 5,
+            ack_wait_secs: 60,
+        }
+    }
+}
+
+impl JetStreamConfig {
+    /// The stream of the recognition results.
+    pub fn stream(&self) -> PublishedStream<'_> {
+        PublishedStream {
+            name: &self.recognition_stream,
+            retention: self.retention,
+            max_age: Duration::from_secs(self.max_age_secs),
+            max_bytes: self.max_bytes,
+        }
+    }
+
+    /// The durable consumer of the frames.
+    pub fn consumer(&self) -> DurableConsumer<'_> {
+        DurableConsumer {
+            stream: &self.frames_stream,
+            durable_name: &self.durable_name,
+            max_deliver: self.max_deliver,
+            ack_wait: Duration::from_secs(self.ack_wait_secs),
+        }
+    }
+}
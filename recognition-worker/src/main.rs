@@ -1,20 +1,45 @@
+pub(crate) mod batch;
 pub(crate) mod config;
+pub(crate) mod jetstream;
+pub(crate) mod metrics;
+pub(crate) mod model;
+pub(crate) mod partition;
+pub(crate) mod queue;
 pub(crate) mod recognizer;
+pub(crate) mod reload;
+pub(crate) mod server;
 
 use anyhow::Context;
 use async_nats::HeaderMap;
+use batch::BatchScheduler;
 use config::RecognitionConfig;
 use futures::StreamExt as _;
+use iot_common::jetstream::Publisher;
+use iot_common::shutdown::shutdown_signal;
+use jetstream::JetStreamConfig;
+use metrics::Metrics;
+use model::YoloModel;
+use queue::{FrameQueue, Shed};
 use recognizer::{RecognitionPayload, RecognitionWorker};
-use std::sync::Arc;
-use tokio_util::task::TaskTracker;
-use yolo_rs::model::YoloModelSession;
+use reload::{ModelReloader, ModelSlot};
+use std::{sync::Arc, time::Duration};
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     tracing_subscriber::fmt::init();
 
-    let RecognitionConfig { nats_url } = config::parse_config()?;
+    let RecognitionConfig {
+        nats_url,
+        jetstream,
+        queue_group,
+        partition,
+        shutdown_timeout_secs,
+        model,
+        batch,
+        queue,
+        server,
+    } = config::parse_config()?;
 
     // Initialize ONNX runtime
     ort::init()
@@ -38,51 +63,136 @@ async fn main() -> anyhow::Result<()> {
 
     let task_tracker = TaskTracker::new();
 
-    let mut frame_subscriber = nats_client.subscribe("frames").await?;
+    // A partitioned worker receives all the frames, and skips the ones of the other partitions.
+    let queue_group = partition.is_none().then_some(queue_group.as_str());
+    // The stream of the results is created before waiting for the stream of the frames.
+    let publisher = Publisher::new(
+        nats_client.clone(),
+        "recognition",
+        jetstream.as_ref().map(JetStreamConfig::stream),
+    )
+    .await?;
+    let mut frame_subscriber = iot_common::jetstream::subscribe(
+        &nats_client,
+        "frames",
+        jetstream.as_ref().map(JetStreamConfig::consumer),
+        queue_group,
+    )
+    .await?;
+
+    let watch_interval_secs = model.watch_interval_secs;
+    let yolo_model = YoloModel::load(model)?;
+    yolo_model.smoke_test()?;
+    let yolo_model = Arc::new(ModelSlot::new(yolo_model));
+    let worker = RecognitionWorker::new(yolo_model.clone());
+
+    // The frames wait in a bounded queue for a fixed pool of inference threads.
+    let metrics = Arc::new(Metrics::new()?);
+    let frame_queue = Arc::new(FrameQueue::new(queue, metrics.clone()));
+    let scheduler = BatchScheduler::start(batch, frame_queue, worker, metrics.clone())?;
+
+    let shutdown = CancellationToken::new();
+
+    // The server runs until the shutdown, so it is not tracked.
+    tokio::spawn({
+        let shutdown = shutdown.clone();
+        let metrics = metrics.clone();
+        async move {
+            if let Err(e) = server::serve(server, metrics, shutdown).await {
+                tracing::error!("The HTTP server stopped: {:?}", e);
+            }
+        }
+    });
+
+    // Swap the model on the reload requests or the changes of its file; the frames in flight
+    // finish on the previous model.
+    let reloader = Arc::new(ModelReloader::new(yolo_model));
+    let reload_subscriber = nats_client
+        .subscribe(reload::RELOAD_SUBJECT)
+        .await
+        .context("Failed to subscribe to the reload requests")?;
+    task_tracker.spawn(reload::listen_reload_requests(
+        nats_client.clone(),
+        reload_subscriber,
+        reloader.clone(),
+        shutdown.clone(),
+    ));
+    if watch_interval_secs > 0 {
+        task_tracker.spawn(reload::watch_model_file(
+            reloader,
+            Duration::from_secs(watch_interval_secs),
+            shutdown.clone(),
+        ));
+    }
 
-    let yolo_model = Arc::new(
-        YoloModelSession::from_filename_v8("models/yolo11x.onnx")
-            .expect("failed to load YOLO model"),
-    );
-    let worker = RecognitionWorker::new(yolo_model);
+    let signal = shutdown_signal();
+    tokio::pin!(signal);
 
-    while let Some(frame_message) = frame_subscriber.next().await {
-        tracing::debug!("Received a frame message.");
+    loop {
+        // Stop receiving the frames on shutdown. The unacknowledged ones are redelivered in JetStream mode.
+        let (frame_message, acknowledger) = tokio::select! {
+            message = frame_subscriber.next() => match message {
+                Some(message) => message,
+                None => break,
+            },
+            _ = &mut signal => break,
+        };
 
-        let worker = worker.clone(); // cheap clone
-        let task_tracker_clone = task_tracker.clone();
-        let nats_client = nats_client.clone();
+        tracing::debug!("Received a frame message.");
 
-        task_tracker.spawn(async move {
-            let payload: RecognitionPayload = match frame_message.try_into() {
-                Ok(payload) => payload,
-                Err(e) => {
-                    tracing::warn!("Failed to parse the message: {:?}; skipping.", e);
-                    return;
-                }
-            };
-            let frame_id = payload.frame_id.clone();
+        if let Some(partition) = &partition {
+            let monitor_id = frame_message
+                .headers
+                .as_ref()
+                .and_then(|headers| headers.get("Monitor-Id"))
+                .map(|monitor_id| monitor_id.as_str());
+            if !partition.owns(monitor_id) {
+                continue;
+            }
+        }
+
+        let payload: RecognitionPayload = match frame_message.try_into() {
+            Ok(payload) => payload,
+            Err(e) => {
+                tracing::warn!("Failed to parse the message: {:?}; skipping.", e);
+                task_tracker.spawn(async move { acknowledger.term().await });
+                continue;
+            }
+        };
+        let frame_id = payload.frame_id.clone();
+        let created_at = payload.created_at;
+        let published_at = payload.published_at;
+        metrics.record_received(payload.monitor_id.as_deref().unwrap_or_default());
 
-            let results = task_tracker_clone
-                .spawn_blocking(move || worker.recognize(payload))
-                .await;
+        // Queue the frame right away, so the frames are recognized in the order they are received.
+        let recognition = scheduler.submit(payload);
+        let publisher = publisher.clone();
 
-            let results = match results {
-                Ok(Ok(results)) => results,
-                Ok(Err(e)) => {
-                    tracing::warn!("Failed to recognize the payload: {:?}; skipping.", e);
+        task_tracker.spawn(async move {
+            let results = match recognition.await {
+                Ok(results) => results,
+                // A shed frame is not redelivered, since it would be as late as it is now.
+                Err(e) if e.is::<Shed>() => {
+                    acknowledger.term().await;
                     return;
                 }
                 Err(e) => {
-                    tracing::error!(
-                        "Failed to create a thread to recognize the picture: {:?}. It should not happened :(",
-                        e
-                    );
+                    tracing::warn!("Failed to recognize the payload: {:?}; skipping.", e);
+                    acknowledger.nak().await;
                     return;
                 }
             };
 
-            tracing::info!("Publishing the results to NATS.");
+            // the end-to-end latency since the frame was captured and published
+            let now = chrono::Utc::now();
+            let capture_latency_ms = (now - created_at.to_utc()).num_milliseconds();
+            let publish_latency_ms = published_at.map(|at| (now - at.to_utc()).num_milliseconds());
+
+            tracing::info!(
+                capture_latency_ms,
+                publish_latency_ms,
+                "Publishing the results to NATS."
+            );
 
             let mut header = HeaderMap::new();
             header.append("Content-Type", "application/json");
@@ -94,20 +204,50 @@ async fn main() -> anyhow::Result<()> {
             let serde_results = match serialized_result {
                 Ok(serde_results) => serde_results,
                 Err(e) => {
-                    tracing::error!("Failed to serialize the results: {:?}. It should not happened :(", e);
+                    tracing::error!(
+                        "Failed to serialize the results: {:?}. It should not happened :(",
+                        e
+                    );
+                    acknowledger.term().await;
                     return;
                 }
             };
 
-            let publish_result = nats_client.publish_with_headers("recognition", header, serde_results.into()).await;
+            let publish_result = publisher
+                .publish("recognition", header, serde_results.into())
+                .await;
             if let Err(e) = publish_result {
                 tracing::warn!("Failed to publish the results: {:?}.", e);
+                acknowledger.nak().await;
+                return;
             }
+
+            acknowledger.ack().await;
         });
     }
 
+    drop(frame_subscriber);
+    shutdown.cancel();
+
+    // Drain the in-flight recognitions and their publishes.
     task_tracker.close();
-    task_tracker.wait().await;
+    if tokio::time::timeout(
+        Duration::from_secs(shutdown_timeout_secs),
+        task_tracker.wait(),
+    )
+    .await
+    .is_err()
+    {
+        tracing::warn!(
+            "Timed out waiting for {} recognitions to finish; dropping them.",
+            task_tracker.len()
+        );
+    }
+    scheduler.close();
+
+    if let Err(e) = nats_client.flush().await {
+        tracing::error!("Failed to flush the NATS client: {:?}", e);
+    }
 
     Ok(())
 }
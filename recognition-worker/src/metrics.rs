@@ -0,0 +1,81 @@
+use anyhow::Context;
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, IntCounterVec, IntGauge, Opts, Registry, TextEncoder,
+};
+
+use crate::queue::ShedReason;
+
+/// The Prometheus metrics of the worker.
+pub struct Metrics {
+    registry: Registry,
+    frames_received: IntCounterVec,
+    frames_shed: IntCounterVec,
+    pub queue_depth: IntGauge,
+    pub batch_size: Histogram,
+}
+
+impl Metrics {
+    pub fn new() -> anyhow::Result<Self> {
+        let registry = Registry::new_custom(Some("recognition".to_string()), None)
+            .context("Failed to create the metrics registry")?;
+
+        let frames_received = IntCounterVec::new(
+            Opts::new(
+                "frames_received_total",
+                "The number of frames received from NATS.",
+            ),
+            &["monitor"],
+        )?;
+        let frames_shed = IntCounterVec::new(
+            Opts::new(
+                "frames_shed_total",
+                "The number of frames dropped without being recognized.",
+            ),
+            &["monitor", "reason"],
+        )?;
+        let queue_depth = IntGauge::new(
+            "queue_depth",
+            "The number of frames waiting for the inference.",
+        )?;
+        let batch_size = Histogram::with_opts(
+            HistogramOpts::new("batch_size", "The number of frames in an inference batch.")
+                .buckets(vec![1.0, 2.0, 4.0, 8.0, 16.0, 32.0, 64.0]),
+        )?;
+
+        registry.register(Box::new(frames_received.clone()))?;
+        registry.register(Box::new(frames_shed.clone()))?;
+        registry.register(Box::new(queue_depth.clone()))?;
+        registry.register(Box::new(batch_size.clone()))?;
+
+        Ok(Self {
+            registry,
+            frames_received,
+            frames_shed,
+            queue_depth,
+            batch_size,
+        })
+    }
+
+    pub fn record_received(&self, monitor_id: &str) {
+        self.frames_received.with_label_values(&[monitor_id]).inc();
+    }
+
+    /// Count a shed frame, returning the number of the frames of the monitor shed for the reason.
+    pub fn record_shed(&self, monitor_id: &str, reason: ShedReason) -> u64 {
+        let counter = self
+            .frames_shed
+            .with_label_values(&[monitor_id, reason.as_str()]);
+        counter.inc();
+        counter.get()
+    }
+
+    /// Encode the metrics in the Prometheus text format.
+    pub fn encode(&self) -> anyhow::Result<String> {
+        let mut buffer = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .context("Failed to encode the metrics")?;
+
+        String::from_utf8(buffer).context("The metrics are not in UTF-8")
+    }
+}
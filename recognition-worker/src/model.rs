@@ -0,0 +1,625 @@
+use std::{path::PathBuf, sync::Arc};
+
+use anyhow::Context;
+use image::{DynamicImage, GenericImageView, Rgba, imageops::FilterType};
+use ndarray::{Array3, Array4, ArrayView2, Axis, s};
+use ort::{inputs, session::Session};
+use sha2::{Digest, Sha256};
+
+/// The labels of the COCO dataset, which the pretrained YOLO models are trained on.
+const COCO_LABELS: &[&str] = &[
+    "person",
+    "bicycle",
+    "car",
+    "motorcycle",
+    "airplane",
+    "bus",
+    "train",
+    "truck",
+    "boat",
+    "traffic light",
+    "fire hydrant",
+    "stop sign",
+    "parking meter",
+    "bench",
+    "bird",
+    "cat",
+    "dog",
+    "horse",
+    "sheep",
+    "cow",
+    "elephant",
+    "bear",
+    "zebra",
+    "giraffe",
+    "backpack",
+    "umbrella",
+    "handbag",
+    "tie",
+    "suitcase",
+    "frisbee",
+    "skis",
+    "snowboard",
+    "sports ball",
+    "kite",
+    "baseball bat",
+    "baseball glove",
+    "skateboard",
+    "surfboard",
+    "tennis racket",
+    "bottle",
+    "wine glass",
+    "cup",
+    "fork",
+    "knife",
+    "spoon",
+    "bowl",
+    "banana",
+    "apple",
+    "sandwich",
+    "orange",
+    "broccoli",
+    "carrot",
+    "hot dog",
+    "pizza",
+    "donut",
+    "cake",
+    "chair",
+    "couch",
+    "potted plant",
+    "bed",
+    "dining table",
+    "toilet",
+    "tv",
+    "laptop",
+    "mouse",
+    "remote",
+    "keyboard",
+    "cell phone",
+    "microwave",
+    "oven",
+    "toaster",
+    "sink",
+    "refrigerator",
+    "book",
+    "clock",
+    "vase",
+    "scissors",
+    "teddy bear",
+    "hair drier",
+    "toothbrush",
+];
+
+/// The YOLO version the model is exported from, which decides the layout of its output.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum YoloVersion {
+    /// `(1, anchors, 5 + classes)`: the box, the objectness, then the class scores.
+    V5,
+
+    /// `(1, 4 + classes, anchors)`: the box, then the class scores.
+    V8,
+
+    /// The same layout as [`YoloVersion::V8`].
+    #[default]
+    V11,
+}
+
+/// The configuration of the YOLO model.
+#[derive(Clone, Debug, serde::Deserialize)]
+#[serde(default)]
+pub struct ModelConfig {
+    /// The path of the ONNX model.
+    pub path: PathBuf,
+
+    /// The YOLO version the model is exported from.
+    pub version: YoloVersion,
+
+    /// The width and height of the input of the model, in pixels.
+    pub input_size: u32,
+
+    /// The file of the labels, one per line in the order of the class IDs.
+    /// The COCO labels are used if unset.
+    pub labels_file: Option<PathBuf>,
+
+    /// The minimum confidence of a detection, from 0.0 to 1.0.
+    pub confidence_threshold: f32,
+
+    /// The maximum IoU between two detections before the less confident one is suppressed,
+    /// from 0.0 to 1.0.
+    pub iou_threshold: f32,
+
+    /// How often to check the model file for changes to reload it, in seconds. `0` disables it.
+    pub watch_interval_secs: u64,
+
+    /// The directory of the model and labels files a reload request may switch to.
+    /// The reload requests cannot switch the files if unset.
+    pub models_directory: Option<PathBuf>,
+}
+
+impl Default for ModelConfig {
+    fn default() -> Self {
+        Self {
+            path: PathBuf::from("models/yolo11x.onnx"),
+            version: YoloVersion::default(),
+            input_size: 640,
+            labels_file: None,
+            confidence_threshold: 0.5,
+            iou_threshold: 0.7,
+            watch_interval_secs: 10,
+            models_directory: None,
+        }
+    }
+}
+
+impl ModelConfig {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.input_size == 0 || !self.input_size.is_multiple_of(32) {
+            anyhow::bail!("model.input_size should be a positive multiple of 32");
+        }
+        for (name, value) in [
+            ("confidence_threshold", self.confidence_threshold),
+            ("iou_threshold", self.iou_threshold),
+        ] {
+            if !(0.0..=1.0).contains(&value) {
+                anyhow::bail!("model.{name} should be in the range of 0.0 to 1.0");
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct BoundingBox {
+    pub x1: f32,
+    pub y1: f32,
+    pub x2: f32,
+    pub y2: f32,
+}
+
+impl BoundingBox {
+    fn area(&self) -> f32 {
+        (self.x2 - self.x1) * (self.y2 - self.y1)
+    }
+
+    fn iou(&self, other: &Self) -> f32 {
+        let intersection = (self.x2.min(other.x2) - self.x1.max(other.x1)).max(0.0)
+            * (self.y2.min(other.y2) - self.y1.max(other.y1)).max(0.0);
+
+        intersection / (self.area() + other.area() - intersection)
+    }
+}
+
+/// An entity detected in a picture.
+#[derive(Debug, Clone)]
+pub struct Detection {
+    /// The bounding box in the pixels of the original picture.
+    pub bounding_box: BoundingBox,
+    pub label: Arc<str>,
+    pub confidence: f32,
+}
+
+/// A picture prepared for the model by [`YoloModel::prepare`].
+pub struct ModelInput {
+    /// The `(3, size, size)` RGB tensor in the range of 0.0 to 1.0.
+    tensor: Array3<f32>,
+    raw_width: u32,
+    raw_height: u32,
+}
+
+/// The YOLO model, with its labels.
+pub struct YoloModel {
+    session: Session,
+    config: ModelConfig,
+    labels: Vec<Arc<str>>,
+    /// The batch size the model is exported with, or `None` if it takes any batch size.
+    batch_size: Option<usize>,
+    /// The file name and the digest of the model, e.g. `yolo11x.onnx@3f9a0c1b2d4e`.
+    version: Arc<str>,
+}
+
+impl YoloModel {
+    /// Load the ONNX model and its labels.
+    pub fn load(config: ModelConfig) -> anyhow::Result<Self> {
+        let labels = match &config.labels_file {
+            Some(labels_file) => {
+                let labels = std::fs::read_to_string(labels_file)
+                    .with_context(|| format!("Failed to read the labels file {labels_file:?}"))?;
+                labels
+                    .lines()
+                    .map(str::trim)
+                    .filter(|label| !label.is_empty())
+                    .map(Arc::from)
+                    .collect::<Vec<_>>()
+            }
+            None => COCO_LABELS.iter().copied().map(Arc::from).collect(),
+        };
+        if labels.is_empty() {
+            anyhow::bail!("The labels file {:?} has no labels", config.labels_file);
+        }
+
+        // Load the model from the same bytes as the digest, in case the file is replaced meanwhile.
+        let model = std::fs::read(&config.path)
+            .with_context(|| format!("Failed to read the YOLO model {:?}", config.path))?;
+        let digest = format!("{:x}", Sha256::digest(&model));
+        let file_name = config
+            .path
+            .file_name()
+            .map(|file_name| file_name.to_string_lossy())
+            .unwrap_or_default();
+        let version = Arc::from(format!("{file_name}@{}", &digest[..12]));
+
+        let session = Session::builder()
+            .context("Failed to create the ONNX session")?
+            .commit_from_memory(&model)
+            .with_context(|| format!("Failed to load the YOLO model {:?}", config.path))?;
+
+        // (batch, channels, height, width), where a dynamic dimension is -1
+        let dimensions = session
+            .inputs
+            .first()
+            .and_then(|input| input.input_type.tensor_dimensions())
+            .filter(|dimensions| dimensions.len() == 4)
+            .context("The YOLO model should take a 4-dimensional tensor")?;
+        let input_size = i64::from(config.input_size);
+        if dimensions[2..].iter().any(|&d| d > 0 && d != input_size) {
+            anyhow::bail!(
+                "The YOLO model takes {}x{} pictures, but the input size is {}",
+                dimensions[3],
+                dimensions[2],
+                config.input_size
+            );
+        }
+        let batch_size = (dimensions[0] > 0).then_some(dimensions[0] as usize);
+
+        tracing::info!(
+            "Loaded the YOLO model {} ({:?}, {}x{}, batch size {}) with {} labels",
+            version,
+            config.version,
+            config.input_size,
+            config.input_size,
+            batch_size.map_or("dynamic".to_string(), |size| size.to_string()),
+            labels.len()
+        );
+
+        Ok(Self {
+            session,
+            config,
+            labels,
+            batch_size,
+            version,
+        })
+    }
+
+    /// The version of the model, which the results are tagged with.
+    pub fn version(&self) -> &Arc<str> {
+        &self.version
+    }
+
+    pub fn config(&self) -> &ModelConfig {
+        &self.config
+    }
+
+    /// Run an inference on a blank picture, to make sure the model works before using it.
+    pub fn smoke_test(&self) -> anyhow::Result<()> {
+        let size = self.config.input_size;
+        let image = DynamicImage::new_rgb8(size, size);
+
+        self.detect(&image)
+            .with_context(|| format!("The smoke inference of {} failed", self.version))?;
+
+        Ok(())
+    }
+
+    /// Detect the entities in the picture.
+    pub fn detect(&self, image: &DynamicImage) -> anyhow::Result<Vec<Detection>> {
+        let input = self.prepare(image);
+        let mut detections = self.detect_batch(&[&input])?;
+
+        Ok(detections.pop().unwrap_or_default())
+    }
+
+    /// Resize the picture to the input size, and normalize it for the model.
+    pub fn prepare(&self, image: &DynamicImage) -> ModelInput {
+        let size = self.config.input_size;
+        let mut tensor = Array3::zeros((3, size as usize, size as usize));
+
+        let resized = image.resize_exact(size, size, FilterType::CatmullRom);
+        for (x, y, Rgba([r, g, b, _])) in resized.pixels() {
+            let (x, y) = (x as usize, y as usize);
+
+            tensor[[0, y, x]] = r as f32 / 255.;
+            tensor[[1, y, x]] = g as f32 / 255.;
+            tensor[[2, y, x]] = b as f32 / 255.;
+        }
+
+        ModelInput {
+            tensor,
+            raw_width: image.width(),
+            raw_height: image.height(),
+        }
+    }
+
+    /// Detect the entities in the pictures, in batches of the size the model is exported with.
+    ///
+    /// Returns the detections of each picture, in the same order.
+    pub fn detect_batch(&self, inputs: &[&ModelInput]) -> anyhow::Result<Vec<Vec<Detection>>> {
+        let chunk_size = self.batch_size.unwrap_or(inputs.len()).max(1);
+        let size = self.config.input_size as usize;
+
+        let mut detections = Vec::with_capacity(inputs.len());
+        for chunk in inputs.chunks(chunk_size) {
+            // A model with a fixed batch size takes the last chunk padded with blank pictures.
+            let mut batch = Array4::zeros((chunk_size, 3, size, size));
+            for (index, input) in chunk.iter().enumerate() {
+                batch.slice_mut(s![index, .., .., ..]).assign(&input.tensor);
+            }
+
+            let outputs = self
+                .session
+                .run(
+                    inputs!["images" => batch.view()]
+                        .context("Failed to create the model input")?,
+                )
+                .context("Failed to run the inference")?;
+            let output = outputs["output0"]
+                .try_extract_tensor::<f32>()
+                .context("Failed to extract the model output")?;
+
+            for (index, input) in chunk.iter().enumerate() {
+                let rows = anchor_rows(self.config.version, output.slice(s![index, .., ..]));
+                let candidates = decode(
+                    &self.config,
+                    &self.labels,
+                    rows,
+                    input.raw_width,
+                    input.raw_height,
+                )?;
+                detections.push(non_maximum_suppression(
+                    candidates,
+                    self.config.iou_threshold,
+                ));
+            }
+        }
+
+        Ok(detections)
+    }
+}
+
+/// Lay out the output of a picture as one row per anchor: the box, (the objectness,) then the
+/// class scores.
+fn anchor_rows(version: YoloVersion, output: ArrayView2<f32>) -> ArrayView2<f32> {
+    match version {
+        YoloVersion::V5 => output,
+        YoloVersion::V8 | YoloVersion::V11 => output.reversed_axes(),
+    }
+}
+
+/// Turn the rows of the output into the detections above the confidence threshold.
+fn decode(
+    config: &ModelConfig,
+    labels: &[Arc<str>],
+    rows: ArrayView2<f32>,
+    raw_width: u32,
+    raw_height: u32,
+) -> anyhow::Result<Vec<Detection>> {
+    let scores_offset = match config.version {
+        YoloVersion::V5 => 5,
+        YoloVersion::V8 | YoloVersion::V11 => 4,
+    };
+    let classes = rows.ncols().saturating_sub(scores_offset);
+    if classes > labels.len() {
+        anyhow::bail!(
+            "The model has {} classes, but only {} labels are given",
+            classes,
+            labels.len()
+        );
+    }
+
+    let scale_x = raw_width as f32 / config.input_size as f32;
+    let scale_y = raw_height as f32 / config.input_size as f32;
+
+    let detections = rows
+        .axis_iter(Axis(0))
+        .filter_map(|row| {
+            let (class_id, score) = row
+                .iter()
+                .skip(scores_offset)
+                .copied()
+                .enumerate()
+                .reduce(|best, class| if class.1 > best.1 { class } else { best })?;
+            let confidence = match config.version {
+                YoloVersion::V5 => score * row[4],
+                YoloVersion::V8 | YoloVersion::V11 => score,
+            };
+            if confidence < config.confidence_threshold {
+                return None;
+            }
+
+            let (xc, yc) = (row[0] * scale_x, row[1] * scale_y);
+            let (w, h) = (row[2] * scale_x, row[3] * scale_y);
+
+            // The boxes of the entities at the edges may overflow the picture.
+            let (width, height) = (raw_width as f32, raw_height as f32);
+            Some(Detection {
+                bounding_box: BoundingBox {
+                    x1: (xc - w / 2.).clamp(0., width),
+                    y1: (yc - h / 2.).clamp(0., height),
+                    x2: (xc + w / 2.).clamp(0., width),
+                    y2: (yc + h / 2.).clamp(0., height),
+                },
+                label: labels[class_id].clone(),
+                confidence,
+            })
+        })
+        .collect();
+
+    Ok(detections)
+}
+
+/// Keep the most confident detection among the ones overlapping each other.
+fn non_maximum_suppression(mut detections: Vec<Detection>, iou_threshold: f32) -> Vec<Detection> {
+    detections.sort_unstable_by(|a, b| b.confidence.total_cmp(&a.confidence));
+
+    let mut selected: Vec<Detection> = Vec::with_capacity(detections.len());
+    for detection in detections {
+        if selected
+            .iter()
+            .all(|kept| kept.bounding_box.iou(&detection.bounding_box) < iou_threshold)
+        {
+            selected.push(detection);
+        }
+    }
+
+    selected
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::{Array2, array};
+
+    use super::*;
+
+    fn config(version: YoloVersion) -> ModelConfig {
+        ModelConfig {
+            version,
+            input_size: 100,
+            confidence_threshold: 0.5,
+            ..ModelConfig::default()
+        }
+    }
+
+    fn labels() -> Vec<Arc<str>> {
+        vec![Arc::from("person"), Arc::from("car")]
+    }
+
+    fn bounding_box(x1: f32, y1: f32, x2: f32, y2: f32) -> BoundingBox {
+        BoundingBox { x1, y1, x2, y2 }
+    }
+
+    fn detection(bounding_box: BoundingBox, confidence: f32) -> Detection {
+        Detection {
+            bounding_box,
+            label: Arc::from("person"),
+            confidence,
+        }
+    }
+
+    fn decode_output(version: YoloVersion, output: &Array2<f32>) -> Vec<Detection> {
+        let rows = anchor_rows(version, output.view());
+        decode(&config(version), &labels(), rows, 200, 100).unwrap()
+    }
+
+    #[test]
+    fn decodes_the_rows_of_yolov5_with_the_objectness() {
+        // (anchors, 5 + classes)
+        let output = array![
+            [50., 50., 20., 10., 0.9, 0.2, 1.0],
+            [10., 10., 4., 4., 0.4, 1.0, 0.1],
+        ];
+
+        let detections = decode_output(YoloVersion::V5, &output);
+
+        assert_eq!(detections.len(), 1);
+        assert_eq!(&*detections[0].label, "car");
+        assert!((detections[0].confidence - 0.9).abs() < 1e-6);
+        let BoundingBox { x1, y1, x2, y2 } = detections[0].bounding_box;
+        assert_eq!((x1, y1, x2, y2), (80., 45., 120., 55.));
+    }
+
+    #[test]
+    fn decodes_the_transposed_columns_of_yolov8() {
+        // (4 + classes, anchors)
+        let output = array![
+            [50., 10.],
+            [50., 10.],
+            [20., 4.],
+            [10., 4.],
+            [0.8, 0.3],
+            [0.1, 0.2],
+        ];
+
+        for version in [YoloVersion::V8, YoloVersion::V11] {
+            let detections = decode_output(version, &output);
+
+            assert_eq!(detections.len(), 1);
+            assert_eq!(&*detections[0].label, "person");
+            assert_eq!(detections[0].confidence, 0.8);
+            let BoundingBox { x1, y1, x2, y2 } = detections[0].bounding_box;
+            assert_eq!((x1, y1, x2, y2), (80., 45., 120., 55.));
+        }
+    }
+
+    #[test]
+    fn keeps_the_detections_at_the_confidence_threshold() {
+        let output = array![
+            [50., 50., 20., 10., 0.5, 0.],
+            [50., 50., 20., 10., 0.49, 0.],
+        ]
+        .reversed_axes();
+
+        let detections = decode_output(YoloVersion::V8, &output);
+
+        assert_eq!(detections.len(), 1);
+        assert_eq!(detections[0].confidence, 0.5);
+    }
+
+    #[test]
+    fn clamps_the_boxes_to_the_picture() {
+        let output = array![[2., 98., 10., 10., 0.9, 0.]].reversed_axes();
+
+        let detections = decode_output(YoloVersion::V8, &output);
+
+        let BoundingBox { x1, y1, x2, y2 } = detections[0].bounding_box;
+        assert_eq!((x1, y1, x2, y2), (0., 93., 14., 100.));
+    }
+
+    #[test]
+    fn rejects_more_classes_than_labels() {
+        let output = array![[50., 50., 20., 10., 0.9, 0.1, 0.1, 0.1]];
+
+        let rows = anchor_rows(YoloVersion::V5, output.view());
+
+        assert!(decode(&config(YoloVersion::V5), &labels(), rows, 200, 100).is_err());
+    }
+
+    #[test]
+    fn iou_of_disjoint_boxes_is_zero() {
+        let a = bounding_box(0., 0., 10., 10.);
+        let b = bounding_box(20., 20., 30., 30.);
+
+        assert_eq!(a.iou(&b), 0.);
+    }
+
+    #[test]
+    fn iou_of_a_contained_box_is_the_ratio_of_the_areas() {
+        let outer = bounding_box(0., 0., 10., 10.);
+        let inner = bounding_box(0., 0., 5., 5.);
+
+        assert_eq!(outer.iou(&inner), 0.25);
+        assert_eq!(inner.iou(&outer), 0.25);
+    }
+
+    #[test]
+    fn iou_of_identical_boxes_is_one() {
+        let a = bounding_box(0., 0., 10., 10.);
+
+        assert_eq!(a.iou(&a), 1.);
+    }
+
+    #[test]
+    fn nms_keeps_the_most_confident_box_of_each_group() {
+        let detections = vec![
+            detection(bounding_box(0., 0., 10., 10.), 0.6),
+            detection(bounding_box(1., 1., 11., 11.), 0.9),
+            detection(bounding_box(50., 50., 60., 60.), 0.7),
+            detection(bounding_box(51., 50., 61., 60.), 0.8),
+        ];
+
+        let kept = non_maximum_suppression(detections, 0.5);
+
+        let confidences = kept.iter().map(|d| d.confidence).collect::<Vec<_>>();
+        assert_eq!(confidences, [0.9, 0.8]);
+    }
+}
@@ -0,0 +1,101 @@
+/// The configuration of the per-monitor stickiness.
+///
+/// The monitors are hashed into `count` partitions, and the worker only recognizes
+/// the frames of the monitors in its partition, so the frames of a monitor always
+/// go to the same worker. Each partition should have exactly one worker.
+#[derive(Clone, Debug, serde::Deserialize)]
+pub struct PartitionConfig {
+    /// The number of partitions, usually the number of workers.
+    pub count: u32,
+
+    /// The partition of this worker, from `0` to `count - 1`.
+    pub index: u32,
+}
+
+impl PartitionConfig {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.count == 0 {
+            anyhow::bail!("partition.count should be greater than 0");
+        }
+        if self.index >= self.count {
+            anyhow::bail!("partition.index should be less than partition.count");
+        }
+
+        Ok(())
+    }
+
+    /// Whether the frames of the monitor belong to this worker.
+    ///
+    /// The frames without a monitor belong to the partition `0`.
+    pub fn owns(&self, monitor_id: Option<&str>) -> bool {
+        let partition = monitor_id.map_or(0, |monitor_id| fnv1a(monitor_id) % self.count as u64);
+
+        partition == self.index as u64
+    }
+}
+
+/// The 64-bit FNV-1a hash, which is the same on every worker, unlike the [`std::hash`] hashers.
+fn fnv1a(value: &str) -> u64 {
+    value.bytes().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn partitions(count: u32) -> Vec<PartitionConfig> {
+        (0..count)
+            .map(|index| PartitionConfig { count, index })
+            .collect()
+    }
+
+    #[test]
+    fn fnv1a_matches_the_reference_vectors() {
+        assert_eq!(fnv1a(""), 0xcbf29ce484222325);
+        assert_eq!(fnv1a("a"), 0xaf63dc4c8601ec8c);
+        assert_eq!(fnv1a("foobar"), 0x85944171f73967e8);
+    }
+
+    #[test]
+    fn each_monitor_is_owned_by_exactly_one_partition() {
+        for count in [1, 2, 3, 8] {
+            let partitions = partitions(count);
+
+            for monitor in 0..100 {
+                let monitor_id = format!("camera-{monitor}");
+                let owners = partitions
+                    .iter()
+                    .filter(|partition| partition.owns(Some(&monitor_id)))
+                    .count();
+
+                assert_eq!(owners, 1, "{monitor_id} of {count} partitions");
+            }
+        }
+    }
+
+    #[test]
+    fn the_frames_without_a_monitor_belong_to_the_first_partition() {
+        let partitions = partitions(4);
+
+        assert!(partitions[0].owns(None));
+        assert!(
+            partitions[1..]
+                .iter()
+                .all(|partition| !partition.owns(None))
+        );
+    }
+
+    #[test]
+    fn spreads_the_monitors_over_the_partitions() {
+        let partitions = partitions(4);
+
+        for partition in &partitions {
+            let owned = (0..100)
+                .filter(|monitor| partition.owns(Some(&format!("camera-{monitor}"))))
+                .count();
+            assert!(owned > 0, "partition {} owns no monitor", partition.index);
+        }
+    }
+}
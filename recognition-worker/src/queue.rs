@@ -0,0 +1,433 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
+    sync::{Arc, Condvar, Mutex},
+    time::{Duration, Instant},
+};
+
+use tokio::sync::oneshot;
+
+use crate::{
+    metrics::Metrics,
+    recognizer::{RecognitionPayload, RecognitionResult},
+};
+
+/// The configuration of the queue between NATS and the inference pool.
+#[derive(Clone, Debug, serde::Deserialize)]
+#[serde(default)]
+pub struct QueueConfig {
+    /// The maximum number of frames waiting for the inference.
+    pub capacity: usize,
+
+    /// What to do when the queue is full.
+    pub overflow: OverflowPolicy,
+
+    /// Drop the frames captured longer ago than this, in milliseconds, according to their `Date` header.
+    pub max_staleness_ms: Option<u64>,
+}
+
+impl Default for QueueConfig {
+    fn default() -> Self {
+        Self {
+            capacity: 64,
+            overflow: OverflowPolicy::default(),
+            max_staleness_ms: None,
+        }
+    }
+}
+
+impl QueueConfig {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.capacity == 0 {
+            anyhow::bail!("queue.capacity should be greater than 0");
+        }
+
+        Ok(())
+    }
+}
+
+/// What to do with a frame when the queue is full.
+#[derive(Clone, Copy, Debug, Default, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[allow(clippy::enum_variant_names)] // named after the extractor's `queue.overflow`
+pub enum OverflowPolicy {
+    /// Drop the new frame.
+    DropNewest,
+
+    /// Drop the oldest frame in the queue to make room for the new frame.
+    DropOldest,
+
+    /// Drop the oldest frame of the monitor with the most frames in the queue,
+    /// so a busy monitor does not crowd out the others.
+    #[default]
+    DropOldestPerMonitor,
+}
+
+/// Why a frame is shed without being recognized.
+#[derive(Clone, Copy, Debug)]
+pub enum ShedReason {
+    QueueFull,
+    Stale,
+}
+
+impl ShedReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::QueueFull => "queue_full",
+            Self::Stale => "stale",
+        }
+    }
+}
+
+/// The error of a frame shed without being recognized, to keep up with the incoming frames.
+///
+/// The frame should not be redelivered.
+#[derive(Debug)]
+pub struct Shed(pub ShedReason);
+
+impl fmt::Display for Shed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "The frame is shed ({})", self.0.as_str())
+    }
+}
+
+impl std::error::Error for Shed {}
+
+/// A frame waiting for the inference.
+pub struct QueuedFrame {
+    pub payload: RecognitionPayload,
+    pub reply: oneshot::Sender<anyhow::Result<Vec<RecognitionResult>>>,
+    enqueued_at: Instant,
+}
+
+impl QueuedFrame {
+    pub fn new(
+        payload: RecognitionPayload,
+        reply: oneshot::Sender<anyhow::Result<Vec<RecognitionResult>>>,
+    ) -> Self {
+        Self {
+            payload,
+            reply,
+            enqueued_at: Instant::now(),
+        }
+    }
+}
+
+#[derive(Default)]
+struct QueueState {
+    frames: VecDeque<QueuedFrame>,
+    closed: bool,
+}
+
+/// The bounded queue of the frames waiting for the inference, shared by the inference threads.
+pub struct FrameQueue {
+    config: QueueConfig,
+    state: Mutex<QueueState>,
+    pushed: Condvar,
+    metrics: Arc<Metrics>,
+}
+
+impl FrameQueue {
+    pub fn new(config: QueueConfig, metrics: Arc<Metrics>) -> Self {
+        Self {
+            config,
+            state: Mutex::default(),
+            pushed: Condvar::new(),
+            metrics,
+        }
+    }
+
+    /// Queue the frame, shedding a frame if the queue is full, or the frame if it is stale.
+    ///
+    /// The frame is dropped if the queue is closed.
+    pub fn push(&self, frame: QueuedFrame) {
+        if self.is_stale(&frame.payload) {
+            self.shed(frame, ShedReason::Stale);
+            return;
+        }
+
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        if state.closed {
+            tracing::error!(
+                "The frame queue is closed; dropping frame {}. It should not happened :(",
+                frame.payload.frame_id
+            );
+            return;
+        }
+
+        if state.frames.len() >= self.config.capacity {
+            let evicted = match self.config.overflow {
+                OverflowPolicy::DropNewest => {
+                    drop(state);
+                    self.shed(frame, ShedReason::QueueFull);
+                    return;
+                }
+                OverflowPolicy::DropOldest => state.frames.pop_front(),
+                OverflowPolicy::DropOldestPerMonitor => {
+                    let index = busiest_monitor_oldest_frame(&state.frames);
+                    index.and_then(|index| state.frames.remove(index))
+                }
+            };
+            if let Some(evicted) = evicted {
+                self.shed(evicted, ShedReason::QueueFull);
+            }
+        }
+
+        state.frames.push_back(frame);
+        self.metrics.queue_depth.set(state.frames.len() as i64);
+        drop(state);
+
+        self.pushed.notify_one();
+    }
+
+    /// Wait for up to `max_size` frames, or `max_wait` since the oldest frame is queued.
+    /// The stale frames are shed instead of returned.
+    ///
+    /// Returns `None` once the queue is closed and drained.
+    pub fn next_batch(&self, max_size: usize, max_wait: Duration) -> Option<Vec<QueuedFrame>> {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+
+        let deadline = loop {
+            match state.frames.front() {
+                Some(frame) => break frame.enqueued_at + max_wait,
+                None if state.closed => return None,
+                None => {
+                    state = self.pushed.wait(state).unwrap_or_else(|e| e.into_inner());
+                }
+            }
+        };
+
+        while state.frames.len() < max_size && !state.closed {
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+            state = self
+                .pushed
+                .wait_timeout(state, deadline - now)
+                .unwrap_or_else(|e| e.into_inner())
+                .0;
+        }
+
+        // Another thread may have taken the frames meanwhile.
+        let size = state.frames.len().min(max_size);
+        let frames = state.frames.drain(..size).collect::<Vec<_>>();
+        self.metrics.queue_depth.set(state.frames.len() as i64);
+        drop(state);
+
+        let (stale, frames): (Vec<_>, Vec<_>) = frames
+            .into_iter()
+            .partition(|frame| self.is_stale(&frame.payload));
+        for frame in stale {
+            self.shed(frame, ShedReason::Stale);
+        }
+
+        Some(frames)
+    }
+
+    /// Stop the inference threads once the queued frames are recognized.
+    pub fn close(&self) {
+        self.state.lock().unwrap_or_else(|e| e.into_inner()).closed = true;
+        self.pushed.notify_all();
+    }
+
+    fn is_stale(&self, payload: &RecognitionPayload) -> bool {
+        let Some(max_staleness_ms) = self.config.max_staleness_ms else {
+            return false;
+        };
+
+        let age = chrono::Utc::now() - payload.created_at.to_utc();
+        age.num_milliseconds() > max_staleness_ms as i64
+    }
+
+    fn shed(&self, frame: QueuedFrame, reason: ShedReason) {
+        let monitor_id = frame.payload.monitor_id.as_deref().unwrap_or_default();
+        let shed = self.metrics.record_shed(monitor_id, reason);
+        tracing::warn!(
+            "Shed frame {} of {monitor_id:?} ({}; {shed} frames shed so far).",
+            frame.payload.frame_id,
+            reason.as_str()
+        );
+
+        // the frame may be dropped by then
+        let _ = frame.reply.send(Err(Shed(reason).into()));
+    }
+}
+
+/// The index of the oldest frame of the monitor with the most frames in the queue.
+///
+/// Among the monitors with as many frames, the one with the oldest frame is picked.
+fn busiest_monitor_oldest_frame(frames: &VecDeque<QueuedFrame>) -> Option<usize> {
+    // the number of frames and the index of the oldest frame of each monitor
+    let mut monitors = HashMap::<Option<&str>, (usize, usize)>::new();
+    for (index, frame) in frames.iter().enumerate() {
+        monitors
+            .entry(frame.payload.monitor_id.as_deref())
+            .or_insert((0, index))
+            .0 += 1;
+    }
+
+    monitors
+        .into_values()
+        .max_by(|(count, index), (other_count, other_index)| {
+            count.cmp(other_count).then(other_index.cmp(index))
+        })
+        .map(|(_, index)| index)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Pushed {
+        frame_id: &'static str,
+        results: oneshot::Receiver<anyhow::Result<Vec<RecognitionResult>>>,
+    }
+
+    impl Pushed {
+        /// The reason the frame is shed, if it is.
+        fn shed_reason(&mut self) -> Option<&'static str> {
+            match self.results.try_recv() {
+                Ok(Err(e)) => Some(e.downcast_ref::<Shed>()?.0.as_str()),
+                _ => None,
+            }
+        }
+    }
+
+    fn queue(capacity: usize, overflow: OverflowPolicy) -> FrameQueue {
+        let config = QueueConfig {
+            capacity,
+            overflow,
+            max_staleness_ms: Some(60_000),
+        };
+
+        FrameQueue::new(config, Arc::new(Metrics::new().unwrap()))
+    }
+
+    /// Push a frame of the monitor captured `age_ms` milliseconds ago.
+    fn push(queue: &FrameQueue, frame_id: &'static str, monitor_id: &str, age_ms: i64) -> Pushed {
+        let payload = RecognitionPayload {
+            frame_id: frame_id.to_string(),
+            monitor_id: Some(monitor_id.to_string()),
+            picture: bytes::Bytes::new(),
+            picture_type: image::ImageFormat::WebP,
+            created_at: (chrono::Utc::now() - chrono::Duration::milliseconds(age_ms)).into(),
+            published_at: None,
+        };
+        let (reply, results) = oneshot::channel();
+        queue.push(QueuedFrame::new(payload, reply));
+
+        Pushed { frame_id, results }
+    }
+
+    fn frame_ids(frames: &[QueuedFrame]) -> Vec<&str> {
+        frames
+            .iter()
+            .map(|frame| frame.payload.frame_id.as_str())
+            .collect()
+    }
+
+    fn next_batch(queue: &FrameQueue) -> Vec<QueuedFrame> {
+        queue.next_batch(16, Duration::ZERO).unwrap()
+    }
+
+    #[test]
+    fn drop_newest_sheds_the_new_frame() {
+        let queue = queue(2, OverflowPolicy::DropNewest);
+        let mut a = push(&queue, "a", "front-door", 0);
+        let mut b = push(&queue, "b", "front-door", 0);
+        let mut c = push(&queue, "c", "back-door", 0);
+
+        assert_eq!(a.shed_reason(), None);
+        assert_eq!(b.shed_reason(), None);
+        assert_eq!(c.shed_reason(), Some("queue_full"));
+        assert_eq!(frame_ids(&next_batch(&queue)), [a.frame_id, b.frame_id]);
+    }
+
+    #[test]
+    fn drop_oldest_sheds_the_oldest_frame() {
+        let queue = queue(2, OverflowPolicy::DropOldest);
+        let mut a = push(&queue, "a", "front-door", 0);
+        let mut b = push(&queue, "b", "front-door", 0);
+        let mut c = push(&queue, "c", "back-door", 0);
+
+        assert_eq!(a.shed_reason(), Some("queue_full"));
+        assert_eq!(b.shed_reason(), None);
+        assert_eq!(c.shed_reason(), None);
+        assert_eq!(frame_ids(&next_batch(&queue)), [b.frame_id, c.frame_id]);
+    }
+
+    #[test]
+    fn drop_oldest_per_monitor_sheds_the_busiest_monitor() {
+        let queue = queue(4, OverflowPolicy::DropOldestPerMonitor);
+        let mut a = push(&queue, "a", "back-door", 0);
+        let mut b = push(&queue, "b", "front-door", 0);
+        let mut c = push(&queue, "c", "front-door", 0);
+        let mut d = push(&queue, "d", "front-door", 0);
+        let mut e = push(&queue, "e", "back-door", 0);
+
+        // not the oldest frame of the queue, but the oldest of the busiest monitor
+        assert_eq!(a.shed_reason(), None);
+        assert_eq!(b.shed_reason(), Some("queue_full"));
+        assert_eq!(c.shed_reason(), None);
+        assert_eq!(d.shed_reason(), None);
+        assert_eq!(e.shed_reason(), None);
+        assert_eq!(frame_ids(&next_batch(&queue)), ["a", "c", "d", "e"]);
+    }
+
+    #[test]
+    fn drop_oldest_per_monitor_breaks_ties_by_age() {
+        let queue = queue(4, OverflowPolicy::DropOldestPerMonitor);
+        push(&queue, "a", "front-door", 0);
+        push(&queue, "b", "back-door", 0);
+        push(&queue, "c", "back-door", 0);
+        push(&queue, "d", "front-door", 0);
+        let mut e = push(&queue, "e", "garage", 0);
+
+        assert_eq!(e.shed_reason(), None);
+        assert_eq!(frame_ids(&next_batch(&queue)), ["b", "c", "d", "e"]);
+    }
+
+    #[test]
+    fn sheds_the_stale_frames_on_push() {
+        let queue = queue(4, OverflowPolicy::DropOldestPerMonitor);
+        let mut a = push(&queue, "a", "front-door", 120_000);
+        let mut b = push(&queue, "b", "front-door", 1_000);
+
+        assert_eq!(a.shed_reason(), Some("stale"));
+        assert_eq!(b.shed_reason(), None);
+        assert_eq!(frame_ids(&next_batch(&queue)), [b.frame_id]);
+    }
+
+    #[test]
+    fn sheds_the_frames_gone_stale_in_the_queue() {
+        let queue = queue(4, OverflowPolicy::DropOldestPerMonitor);
+        let mut a = push(&queue, "a", "front-door", 59_950);
+        let mut b = push(&queue, "b", "back-door", 0);
+
+        std::thread::sleep(Duration::from_millis(100));
+
+        assert_eq!(frame_ids(&next_batch(&queue)), [b.frame_id]);
+        assert_eq!(a.shed_reason(), Some("stale"));
+        assert_eq!(b.shed_reason(), None);
+    }
+
+    #[test]
+    fn keeps_every_frame_without_max_staleness() {
+        let queue = FrameQueue::new(QueueConfig::default(), Arc::new(Metrics::new().unwrap()));
+        let mut a = push(&queue, "a", "front-door", 3_600_000);
+
+        assert_eq!(a.shed_reason(), None);
+        assert_eq!(frame_ids(&next_batch(&queue)), [a.frame_id]);
+    }
+
+    #[test]
+    fn drains_the_queue_once_closed() {
+        let queue = queue(4, OverflowPolicy::DropOldestPerMonitor);
+        push(&queue, "a", "front-door", 0);
+        queue.close();
+
+        assert_eq!(frame_ids(&next_batch(&queue)), ["a"]);
+        assert!(queue.next_batch(16, Duration::ZERO).is_none());
+    }
+}
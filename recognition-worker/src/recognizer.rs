@@ -3,9 +3,17 @@ use std::sync::Arc;
 use anyhow::Context as _;
 use async_nats::Message;
 use bytes::Bytes;
-use image::ImageFormat;
+use image::{DynamicImage, ImageFormat};
 use serde::Serialize;
-use yolo_rs::{BoundingBox, image_to_yolo_input_tensor, inference, model::YoloModelSession};
+
+use crate::{
+    model::{BoundingBox, Detection},
+    reload::ModelSlot,
+};
+
+/// The picture types of the frames that the worker can decode.
+const SUPPORTED_PICTURE_TYPES: &[ImageFormat] =
+    &[ImageFormat::Jpeg, ImageFormat::Png, ImageFormat::WebP];
 
 #[derive(Debug, Clone)]
 pub struct RecognitionPayload {
@@ -13,7 +21,10 @@ pub struct RecognitionPayload {
     pub monitor_id: Option<String>,
     pub picture: Bytes,
     pub picture_type: ImageFormat,
+    /// The time when the frame was captured.
     pub created_at: chrono::DateTime<chrono::FixedOffset>,
+    /// The time when the frame was published by the extractor.
+    pub published_at: Option<chrono::DateTime<chrono::FixedOffset>>,
 }
 
 impl TryFrom<Message> for RecognitionPayload {
@@ -21,15 +32,14 @@ impl TryFrom<Message> for RecognitionPayload {
 
     fn try_from(msg: Message) -> Result<Self, Self::Error> {
         let header_map = msg.headers.unwrap_or_default();
-        let content_type = header_map.get("Content-Type").map(|ct| ct.to_string());
+        let content_type = header_map
+            .get("Content-Type")
+            .map(|ct| ct.to_string())
+            .context("missing content type in the message")?;
 
-        if let Some(content_type) = content_type {
-            if content_type != "image/webp" {
-                anyhow::bail!("unsupported content type: {content_type}");
-            }
-        } else {
-            anyhow::bail!("missing content type in the message");
-        }
+        let picture_type = ImageFormat::from_mime_type(&content_type)
+            .filter(|format| SUPPORTED_PICTURE_TYPES.contains(format))
+            .with_context(|| format!("unsupported content type: {content_type}"))?;
 
         let frame_id = header_map
             .get("Frame-Id")
@@ -46,8 +56,13 @@ impl TryFrom<Message> for RecognitionPayload {
             .context("missing Date header")?
             .context("failed to parse Date header")?;
 
+        let published_at = header_map
+            .get("Published-At")
+            .map(|date| chrono::DateTime::parse_from_rfc3339(date.as_str()))
+            .transpose()
+            .context("failed to parse Published-At header")?;
+
         let picture = msg.payload;
-        let picture_type = ImageFormat::WebP;
 
         Ok(Self {
             frame_id,
@@ -55,6 +70,7 @@ impl TryFrom<Message> for RecognitionPayload {
             picture,
             picture_type,
             created_at,
+            published_at,
         })
     }
 }
@@ -69,81 +85,152 @@ pub struct RecognitionResult {
     pub picture: Bytes,
     pub picture_type: ImageFormat,
     pub created_at: chrono::DateTime<chrono::FixedOffset>,
+    /// The version of the model which recognized the entity.
+    pub model_version: String,
 }
 
 #[derive(Clone)]
 pub struct RecognitionWorker {
-    yolo_model: Arc<YoloModelSession>,
+    yolo_model: Arc<ModelSlot>,
 }
 
 impl RecognitionWorker {
-    pub fn new(yolo_model: Arc<YoloModelSession>) -> Self {
+    pub fn new(yolo_model: Arc<ModelSlot>) -> Self {
         Self { yolo_model }
     }
 
-    #[tracing::instrument(skip(self, picture))]
-    pub fn recognize(
+    /// Recognize the frames with a single batched inference.
+    ///
+    /// The frames are decoded and cropped on the calling thread, one of the inference threads.
+    /// Returns the results of each frame, in the same order.
+    pub fn recognize_batch(
         &self,
-        RecognitionPayload {
-            frame_id,
-            monitor_id,
-            picture,
-            picture_type,
-            created_at,
-        }: RecognitionPayload,
-    ) -> anyhow::Result<Vec<RecognitionResult>> {
-        tracing::info!("Recognizing frame {frame_id} from {monitor_id:?}…");
-
-        let image_reader = {
-            let mut reader = image::ImageReader::new(std::io::Cursor::new(picture));
-            reader.set_format(ImageFormat::WebP);
-
-            reader
-        };
-
-        let image = match image_reader.decode() {
-            Ok(frame_data) => frame_data,
+        payloads: Vec<RecognitionPayload>,
+    ) -> Vec<anyhow::Result<Vec<RecognitionResult>>> {
+        // Keep this model even if another one is swapped in meanwhile.
+        let yolo_model = self.yolo_model.current();
+        let model_version = yolo_model.version().to_string();
+
+        let decoded = payloads
+            .iter()
+            .map(|payload| {
+                catch_panic(|| {
+                    let image = decode(payload)?;
+                    let input = yolo_model.prepare(&image);
+
+                    Ok((image, input))
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let inputs = decoded
+            .iter()
+            .filter_map(|decoded| decoded.as_ref().ok())
+            .map(|(_, input)| input)
+            .collect::<Vec<_>>();
+        let mut detections = match catch_panic(|| yolo_model.detect_batch(&inputs)) {
+            Ok(detections) => detections.into_iter(),
             Err(e) => {
-                anyhow::bail!("Failed to decode image: {:?}", e);
+                tracing::warn!("Failed to recognize a batch: {:?}", e);
+                return payloads
+                    .iter()
+                    .map(|_| Err(anyhow::anyhow!("{e:#}")))
+                    .collect();
             }
         };
 
-        let yolo_input = image_to_yolo_input_tensor(&image);
-        let yolo_output =
-            inference(&self.yolo_model, yolo_input.view()).expect("failed to run inference");
-
-        tracing::info!("Found {} entities", yolo_output.len());
-
-        let results = yolo_output
+        payloads
             .into_iter()
-            .map(|entity| {
-                let BoundingBox { x1, x2, y1, y2 } = entity.bounding_box;
-                let label = entity.label;
-                let confidence = entity.confidence;
-
-                let cropped_image =
-                    image.crop_imm(x1 as _, y1 as _, (x2 - x1) as u32, (y2 - y1) as u32);
-
-                // encode the cropped image to WebP
-                let mut buf = Vec::new();
-                let mut cursor = std::io::Cursor::new(&mut buf);
-                cropped_image
-                    .write_to(&mut cursor, ImageFormat::WebP)
-                    .context("Failed to write cropped image to WebP")?;
-
-                Ok(RecognitionResult {
-                    frame_id: frame_id.clone(),
-                    monitor_id: monitor_id.clone(),
-                    label: label.to_string(), // fixme: leverage ArcStr
-                    confidence,
-                    picture: Bytes::from(buf),
-                    picture_type: ImageFormat::WebP,
-                    created_at,
-                })
+            .zip(decoded)
+            .map(|(payload, decoded)| {
+                let (image, _) = decoded?;
+                let yolo_output = detections.next().unwrap_or_default();
+
+                catch_panic(|| crop_entities(payload, &image, yolo_output, &model_version))
             })
-            .collect::<anyhow::Result<Vec<RecognitionResult>>>()?;
+            .collect()
+    }
+}
 
-        tracing::info!("Recognized! Found {} entities.", results.len());
-        Ok(results)
+/// Decode the picture of the frame.
+fn decode(payload: &RecognitionPayload) -> anyhow::Result<DynamicImage> {
+    tracing::info!(
+        "Recognizing frame {} from {:?}…",
+        payload.frame_id,
+        payload.monitor_id
+    );
+
+    let image_reader = {
+        let mut reader = image::ImageReader::new(std::io::Cursor::new(&payload.picture));
+        reader.set_format(payload.picture_type);
+
+        reader
+    };
+
+    match image_reader.decode() {
+        Ok(frame_data) => Ok(frame_data),
+        Err(e) => {
+            anyhow::bail!("Failed to decode image: {:?}", e);
+        }
     }
 }
+
+/// Crop the detected entities from the picture into the results.
+#[tracing::instrument(skip_all, fields(frame_id = %frame_id))]
+fn crop_entities(
+    RecognitionPayload {
+        frame_id,
+        monitor_id,
+        created_at,
+        ..
+    }: RecognitionPayload,
+    image: &DynamicImage,
+    yolo_output: Vec<Detection>,
+    model_version: &str,
+) -> anyhow::Result<Vec<RecognitionResult>> {
+    tracing::info!("Found {} entities with {model_version}", yolo_output.len());
+
+    let results = yolo_output
+        .into_iter()
+        .map(|entity| {
+            let Detection {
+                bounding_box: BoundingBox { x1, x2, y1, y2 },
+                label,
+                confidence,
+            } = entity;
+
+            let cropped_image =
+                image.crop_imm(x1 as _, y1 as _, (x2 - x1) as u32, (y2 - y1) as u32);
+
+            // encode the cropped image to WebP
+            let mut buf = Vec::new();
+            let mut cursor = std::io::Cursor::new(&mut buf);
+            cropped_image
+                .write_to(&mut cursor, ImageFormat::WebP)
+                .context("Failed to write cropped image to WebP")?;
+
+            Ok(RecognitionResult {
+                frame_id: frame_id.clone(),
+                monitor_id: monitor_id.clone(),
+                label: label.to_string(), // fixme: leverage ArcStr
+                confidence,
+                picture: Bytes::from(buf),
+                picture_type: ImageFormat::WebP,
+                created_at,
+                model_version: model_version.to_string(),
+            })
+        })
+        .collect::<anyhow::Result<Vec<RecognitionResult>>>()?;
+
+    tracing::info!("Recognized! Found {} entities.", results.len());
+    Ok(results)
+}
+
+/// Run a step of a frame or a batch, turning its panic into an error so the inference thread survives it.
+fn catch_panic<T>(step: impl FnOnce() -> anyhow::Result<T>) -> anyhow::Result<T> {
+    std::panic::catch_unwind(std::panic::AssertUnwindSafe(step)).unwrap_or_else(|_| {
+        Err(anyhow::anyhow!(
+            "Recognizing the frames panicked. It should not happened :("
+        ))
+    })
+}
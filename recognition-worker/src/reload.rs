@@ -0,0 +1,247 @@
+use std::{
+    path::{Component, Path, PathBuf},
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime},
+};
+
+use anyhow::Context;
+use futures::StreamExt as _;
+use tokio::sync::Mutex;
+use tokio_util::sync::CancellationToken;
+
+use crate::model::{ModelConfig, YoloModel};
+
+/// The subject to request a reload of the model on.
+pub const RELOAD_SUBJECT: &str = "recognition.reload";
+
+/// The model in use, swapped atomically on reload.
+///
+/// A recognition keeps the model it started with, so the frames in flight finish on the old model.
+pub struct ModelSlot(RwLock<Arc<YoloModel>>);
+
+impl ModelSlot {
+    pub fn new(model: YoloModel) -> Self {
+        Self(RwLock::new(Arc::new(model)))
+    }
+
+    /// The current model.
+    pub fn current(&self) -> Arc<YoloModel> {
+        self.0.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Replace the current model, returning the previous one.
+    fn swap(&self, model: Arc<YoloModel>) -> Arc<YoloModel> {
+        std::mem::replace(
+            &mut self.0.write().unwrap_or_else(|e| e.into_inner()),
+            model,
+        )
+    }
+}
+
+/// A request to reload the model, sent to [`RELOAD_SUBJECT`].
+///
+/// An empty message reloads the current model file. The files are switched only
+/// within [`ModelConfig::models_directory`], so the requests cannot load arbitrary files.
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReloadRequest {
+    /// Switch to another model file, by its name in the models directory.
+    pub model_file: Option<PathBuf>,
+
+    /// Switch to another labels file, by its name in the models directory.
+    pub labels_file: Option<PathBuf>,
+}
+
+/// The reply to a [`ReloadRequest`].
+#[derive(Debug, serde::Serialize)]
+struct ReloadReply {
+    /// The version of the model in use after the request.
+    model_version: String,
+
+    /// Why the reload failed, or `None` if it succeeded.
+    error: Option<String>,
+}
+
+/// Reload the model into a [`ModelSlot`], one reload at a time.
+pub struct ModelReloader {
+    slot: Arc<ModelSlot>,
+    /// The configuration of the current model.
+    config: Mutex<ModelConfig>,
+}
+
+impl ModelReloader {
+    pub fn new(slot: Arc<ModelSlot>) -> Self {
+        let config = slot.current().config().clone();
+
+        Self {
+            slot,
+            config: Mutex::new(config),
+        }
+    }
+
+    /// Load the model, and swap it in once it passes a smoke inference.
+    ///
+    /// The current model is kept if the new one fails to load.
+    pub async fn reload(&self, request: ReloadRequest) -> anyhow::Result<Arc<str>> {
+        let mut config = self.config.lock().await;
+
+        let mut new_config = config.clone();
+        if let Some(model_file) = request.model_file {
+            new_config.path = resolve_model_file(&config, &model_file)?;
+        }
+        if let Some(labels_file) = request.labels_file {
+            new_config.labels_file = Some(resolve_model_file(&config, &labels_file)?);
+        }
+
+        let model = tokio::task::spawn_blocking(move || {
+            let model = YoloModel::load(new_config)?;
+            model.smoke_test()?;
+
+            anyhow::Ok(model)
+        })
+        .await
+        .context("Failed to create a thread to load the model")??;
+
+        *config = model.config().clone();
+        let version = model.version().clone();
+        let previous = self.slot.swap(Arc::new(model));
+        tracing::info!("Swapped the model {} for {}.", previous.version(), version);
+
+        Ok(version)
+    }
+
+    async fn path(&self) -> PathBuf {
+        self.config.lock().await.path.clone()
+    }
+}
+
+/// The path of a file requested by its name in the models directory.
+fn resolve_model_file(config: &ModelConfig, file_name: &Path) -> anyhow::Result<PathBuf> {
+    let Some(models_directory) = &config.models_directory else {
+        anyhow::bail!("Switching the files is disabled; set model.models_directory to enable it");
+    };
+
+    let mut components = file_name.components();
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(_)), None) => Ok(models_directory.join(file_name)),
+        _ => anyhow::bail!("{file_name:?} should be a file name in the models directory"),
+    }
+}
+
+/// Reload the model whenever its file changes, until the shutdown.
+pub async fn watch_model_file(
+    reloader: Arc<ModelReloader>,
+    interval: Duration,
+    shutdown: CancellationToken,
+) {
+    let mut ticker = tokio::time::interval(interval);
+    let mut watched = None;
+
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {}
+            _ = shutdown.cancelled() => break,
+        }
+
+        let path = reloader.path().await;
+        let fingerprint = match file_fingerprint(&path).await {
+            Ok(fingerprint) => fingerprint,
+            Err(e) => {
+                tracing::warn!("Failed to check the model file: {:?}", e);
+                continue;
+            }
+        };
+
+        let changed = match &watched {
+            // the path is switched by a reload request, and the model is loaded from it already
+            Some((watched_path, _)) if *watched_path != path => false,
+            Some((_, watched_fingerprint)) => *watched_fingerprint != fingerprint,
+            None => false,
+        };
+        watched = Some((path.clone(), fingerprint));
+        if !changed {
+            continue;
+        }
+
+        // A file still being written fails to load, and is retried once it changes again.
+        tracing::info!("The model file {:?} changed; reloading it.", path);
+        if let Err(e) = reloader.reload(ReloadRequest::default()).await {
+            tracing::warn!(
+                "Failed to reload the model: {:?}; keeping the current one.",
+                e
+            );
+        }
+    }
+}
+
+/// The modification time and the size of the file.
+async fn file_fingerprint(path: &Path) -> anyhow::Result<(SystemTime, u64)> {
+    let metadata = tokio::fs::metadata(path)
+        .await
+        .with_context(|| format!("Failed to read the metadata of {path:?}"))?;
+    let modified = metadata
+        .modified()
+        .context("Failed to get the modification time")?;
+
+    Ok((modified, metadata.len()))
+}
+
+/// Reload the model on the requests to [`RELOAD_SUBJECT`], until the shutdown.
+///
+/// The request is replied with the version of the model in use, if it has a reply subject.
+pub async fn listen_reload_requests(
+    client: async_nats::Client,
+    mut subscriber: async_nats::Subscriber,
+    reloader: Arc<ModelReloader>,
+    shutdown: CancellationToken,
+) {
+    loop {
+        let message = tokio::select! {
+            message = subscriber.next() => match message {
+                Some(message) => message,
+                None => break,
+            },
+            _ = shutdown.cancelled() => break,
+        };
+
+        let request = if message.payload.is_empty() {
+            Ok(ReloadRequest::default())
+        } else {
+            serde_json::from_slice::<ReloadRequest>(&message.payload)
+                .context("Failed to parse the reload request")
+        };
+
+        tracing::info!("Received a reload request: {:?}", request);
+        let error = match request {
+            Ok(request) => reloader.reload(request).await.err(),
+            Err(e) => Some(e),
+        };
+        if let Some(e) = &error {
+            tracing::warn!(
+                "Failed to reload the model: {:?}; keeping the current one.",
+                e
+            );
+        }
+
+        let Some(reply_subject) = message.reply else {
+            continue;
+        };
+        let reply = ReloadReply {
+            model_version: reloader.slot.current().version().to_string(),
+            error: error.map(|e| format!("{e:#}")),
+        };
+        let payload = match serde_json::to_vec(&reply) {
+            Ok(payload) => payload,
+            Err(e) => {
+                tracing::error!(
+                    "Failed to serialize the reload reply: {:?}. It should not happened :(",
+                    e
+                );
+                continue;
+            }
+        };
+        if let Err(e) = client.publish(reply_subject, payload.into()).await {
+            tracing::warn!("Failed to reply to the reload request: {:?}", e);
+        }
+    }
+}
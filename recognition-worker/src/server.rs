@@ -0,0 +1,63 @@
+use std::{net::SocketAddr, str::FromStr, sync::Arc, time::Duration};
+
+use anyhow::Context;
+use poem::{
+    EndpointExt, IntoResponse, Route, Server, get, handler, http::StatusCode,
+    listener::TcpListener, web::Data,
+};
+use tokio_util::sync::CancellationToken;
+
+use crate::metrics::Metrics;
+
+/// The configuration of the HTTP server of the metrics.
+#[derive(Clone, Debug, serde::Deserialize)]
+#[serde(default)]
+pub struct ServerConfig {
+    /// The address to listen on.
+    pub bind_addr: String,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            bind_addr: "0.0.0.0:9091".to_string(),
+        }
+    }
+}
+
+#[handler]
+async fn prometheus_metrics(Data(metrics): Data<&Arc<Metrics>>) -> impl IntoResponse {
+    match metrics.encode() {
+        Ok(body) => body
+            .with_content_type("text/plain; version=0.0.4")
+            .into_response(),
+        Err(e) => {
+            tracing::error!("Failed to encode the metrics: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Serve `/metrics` until the shutdown.
+pub async fn serve(
+    config: ServerConfig,
+    metrics: Arc<Metrics>,
+    shutdown: CancellationToken,
+) -> anyhow::Result<()> {
+    let addr = SocketAddr::from_str(&config.bind_addr).context("Invalid server.bind_addr")?;
+
+    let app = Route::new()
+        .at("/metrics", get(prometheus_metrics))
+        .data(metrics);
+
+    tracing::info!("Serving the metrics on {addr}");
+
+    Server::new(TcpListener::bind(addr))
+        .run_with_graceful_shutdown(
+            app,
+            shutdown.cancelled_owned(),
+            Some(Duration::from_secs(1)),
+        )
+        .await
+        .context("Failed to run the HTTP server")
+}
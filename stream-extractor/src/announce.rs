@@ -0,0 +1,125 @@
+use std::time::Duration;
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use gstreamer::{self as gst};
+use gstreamer_video::{self as gst_video};
+use tokio::sync::watch;
+use tokio_util::sync::CancellationToken;
+
+/// The details of a stream, discovered while it plays.
+#[derive(Clone, Debug, Default, PartialEq, serde::Serialize)]
+pub struct StreamInfo {
+    /// The resolution of the decoded stream, before it is downscaled.
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+
+    /// The frame rate, or `None` if it is variable.
+    pub fps: Option<f64>,
+
+    /// The codec as reported by the parser or demuxer, e.g. `H.264 (Main Profile)`.
+    pub codec: Option<String>,
+}
+
+impl StreamInfo {
+    /// Update the resolution and the frame rate from the caps of the decoded stream.
+    ///
+    /// Returns whether anything changed.
+    pub fn update_from_caps(&mut self, caps: &gst::CapsRef) -> bool {
+        let Ok(video_info) = gst_video::VideoInfo::from_caps(caps) else {
+            return false;
+        };
+
+        let framerate = video_info.fps();
+        let fps = (framerate.numer() > 0 && framerate.denom() > 0)
+            .then(|| framerate.numer() as f64 / framerate.denom() as f64);
+
+        let updated = Self {
+            width: Some(video_info.width()),
+            height: Some(video_info.height()),
+            fps,
+            codec: self.codec.take(),
+        };
+        let changed = updated != *self;
+        *self = updated;
+        changed
+    }
+
+    /// Update the codec from a tag message of the pipeline.
+    ///
+    /// Returns whether anything changed.
+    pub fn update_from_tags(&mut self, tags: &gst::TagListRef) -> bool {
+        let Some(codec) = tags.get::<gst::tags::VideoCodec>() else {
+            return false;
+        };
+        let codec = codec.get().to_string();
+
+        if self.codec.as_ref() == Some(&codec) {
+            return false;
+        }
+        self.codec = Some(codec);
+        true
+    }
+}
+
+/// The announcement of a monitor, published to `monitors.announce`.
+#[derive(Debug, serde::Serialize)]
+struct MonitorAnnouncement<'a> {
+    id: &'a str,
+    name: Option<&'a str>,
+    location: Option<&'a str>,
+    #[serde(flatten)]
+    stream: StreamInfo,
+    /// The version of the stream extractor.
+    version: &'static str,
+    announced_at: DateTime<Utc>,
+}
+
+/// Announce a monitor to `monitors.announce` on startup, whenever the details
+/// of its stream change, and every `interval` until the shutdown.
+pub async fn announce_monitor(
+    client: async_nats::Client,
+    monitor_id: String,
+    name: Option<String>,
+    location: Option<String>,
+    mut stream_info: watch::Receiver<StreamInfo>,
+    interval: Duration,
+    shutdown: CancellationToken,
+) {
+    loop {
+        let announcement = MonitorAnnouncement {
+            id: &monitor_id,
+            name: name.as_deref(),
+            location: location.as_deref(),
+            stream: stream_info.borrow_and_update().clone(),
+            version: env!("CARGO_PKG_VERSION"),
+            announced_at: Utc::now(),
+        };
+
+        if let Err(e) = publish_announcement(&client, &announcement).await {
+            tracing::warn!("Failed to announce monitor {monitor_id}: {:?}", e);
+        }
+
+        tokio::select! {
+            _ = tokio::time::sleep(interval) => {}
+            // once the pipeline is gone, only announce periodically
+            Ok(()) = stream_info.changed() => {}
+            _ = shutdown.cancelled() => break,
+        }
+    }
+}
+
+async fn publish_announcement(
+    client: &async_nats::Client,
+    announcement: &MonitorAnnouncement<'_>,
+) -> anyhow::Result<()> {
+    let payload =
+        serde_json::to_vec(announcement).context("Failed to serialize the announcement")?;
+
+    client
+        .publish("monitors.announce", payload.into())
+        .await
+        .context("Failed to publish the announcement")?;
+
+    Ok(())
+}
@@ -0,0 +1,223 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use futures::StreamExt as _;
+use opendal::{Configurator, Operator, layers::LoggingLayer, services::S3Config};
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
+
+use crate::recorder::Recorder;
+
+/// A request to export a clip around a detection, received on `clips.trigger.<monitor_id>`.
+#[derive(Debug, serde::Deserialize)]
+struct ClipTrigger {
+    /// The ID of the frame of the detection.
+    frame_id: String,
+
+    /// When the frame of the detection was captured.
+    captured_at: DateTime<Utc>,
+}
+
+/// An exported clip, published to `clips`.
+#[derive(Debug, serde::Serialize)]
+struct ClipMessage {
+    monitor_id: String,
+
+    /// The key of the clip in the object storage.
+    clip_id: String,
+
+    /// The frames of the detections covered by the clip.
+    frame_ids: Vec<String>,
+
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+}
+
+/// The frame IDs of a pending clip. They are taken once the clip starts exporting.
+type PendingFrameIds = Arc<Mutex<Option<Vec<String>>>>;
+
+/// Export the clips of a monitor on the triggers, and publish them to `clips`.
+///
+/// The triggers within the range of a pending clip are merged into it,
+/// so consecutive detections of an incident produce a single clip.
+/// The exports are spawned on the tracker, so the shutdown waits for them.
+pub async fn serve_clips(
+    client: async_nats::Client,
+    storage: Arc<Operator>,
+    monitor_id: String,
+    recorder: Recorder,
+    tracker: TaskTracker,
+    shutdown: CancellationToken,
+) -> anyhow::Result<()> {
+    let subject = format!("clips.trigger.{monitor_id}");
+    let mut subscriber = client
+        .subscribe(subject.clone())
+        .await
+        .with_context(|| format!("Failed to subscribe to {subject}"))?;
+
+    let pre = Duration::from_secs(recorder.config().pre_secs);
+    let post = Duration::from_secs(recorder.config().post_secs);
+
+    // The end of the pending clip, and its frame IDs until it starts exporting.
+    let mut pending: Option<(SystemTime, PendingFrameIds)> = None;
+
+    loop {
+        let message = tokio::select! {
+            message = subscriber.next() => message,
+            _ = shutdown.cancelled() => None,
+        };
+        let Some(message) = message else {
+            break;
+        };
+
+        let trigger = match serde_json::from_slice::<ClipTrigger>(&message.payload) {
+            Ok(trigger) => trigger,
+            Err(e) => {
+                tracing::warn!("Failed to parse the clip trigger: {:?}; skipping.", e);
+                continue;
+            }
+        };
+        let captured_at = SystemTime::from(trigger.captured_at);
+
+        if let Some((end, frame_ids)) = &pending
+            && captured_at <= *end
+            && let Some(frame_ids) = frame_ids.lock().unwrap_or_else(|e| e.into_inner()).as_mut()
+        {
+            tracing::debug!("Merging frame {} into the pending clip", trigger.frame_id);
+            frame_ids.push(trigger.frame_id);
+            continue;
+        }
+
+        let start = captured_at - pre;
+        let end = captured_at + post;
+        let frame_ids = Arc::new(Mutex::new(Some(vec![trigger.frame_id])));
+        pending = Some((end, frame_ids.clone()));
+
+        tracker.spawn(export_clip(
+            client.clone(),
+            storage.clone(),
+            monitor_id.clone(),
+            recorder.clone(),
+            (start, end),
+            frame_ids,
+            shutdown.clone(),
+        ));
+    }
+
+    Ok(())
+}
+
+async fn export_clip(
+    client: async_nats::Client,
+    storage: Arc<Operator>,
+    monitor_id: String,
+    recorder: Recorder,
+    (start, end): (SystemTime, SystemTime),
+    frame_ids: PendingFrameIds,
+    shutdown: CancellationToken,
+) {
+    // Wait until the segment containing the end of the clip is closed.
+    // On the shutdown, export what is recorded so far instead.
+    let segment = Duration::from_secs(recorder.config().segment_secs);
+    let ready_at = end + segment + Duration::from_secs(1);
+    if let Ok(delay) = ready_at.duration_since(SystemTime::now()) {
+        tokio::select! {
+            _ = tokio::time::sleep(delay) => {}
+            _ = shutdown.cancelled() => {}
+        }
+    }
+
+    let frame_ids = frame_ids
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .take()
+        .unwrap_or_default();
+
+    let clip_path = match tokio::task::spawn_blocking(move || recorder.export(start, end)).await {
+        Ok(Ok(Some(clip_path))) => clip_path,
+        Ok(Ok(None)) => {
+            tracing::warn!("No recording covers the clip of frames {frame_ids:?}; skipping.");
+            return;
+        }
+        Ok(Err(e)) => {
+            tracing::error!("Failed to export the clip: {:?}", e);
+            return;
+        }
+        Err(e) => {
+            tracing::error!("Failed to create a thread to export the clip: {:?}", e);
+            return;
+        }
+    };
+
+    let result = upload_clip(&storage, &clip_path).await;
+    if let Err(e) = tokio::fs::remove_file(&clip_path).await {
+        tracing::warn!("Failed to remove {clip_path:?}: {:?}", e);
+    }
+    let clip_id = match result {
+        Ok(clip_id) => clip_id,
+        Err(e) => {
+            tracing::error!("Failed to upload the clip: {:?}", e);
+            return;
+        }
+    };
+
+    tracing::info!("Exported clip {clip_id} of frames {frame_ids:?}");
+
+    let message = ClipMessage {
+        monitor_id,
+        clip_id,
+        frame_ids,
+        start: start.into(),
+        end: end.into(),
+    };
+    let payload = match serde_json::to_vec(&message) {
+        Ok(payload) => payload,
+        Err(e) => {
+            tracing::error!(
+                "Failed to serialize the clip message: {:?}. It should not happened :(",
+                e
+            );
+            return;
+        }
+    };
+
+    if let Err(e) = client.publish("clips", payload.into()).await {
+        tracing::error!("Failed to publish the clip to NATS: {:?}", e);
+    }
+}
+
+/// Build the object storage of the clips.
+pub fn build_storage(config: S3Config) -> anyhow::Result<Operator> {
+    let client = config.into_builder();
+
+    let operator = Operator::new(client)
+        .context("Failed to build OpenDAL operator for S3")?
+        .layer(LoggingLayer::default())
+        .finish();
+
+    Ok(operator)
+}
+
+/// Upload the clip to the object storage, returning its key.
+async fn upload_clip(storage: &Operator, clip_path: &std::path::Path) -> anyhow::Result<String> {
+    let clip_id = clip_path
+        .file_name()
+        .context("The clip has no file name")?
+        .to_string_lossy()
+        .to_string();
+
+    let content = tokio::fs::read(clip_path)
+        .await
+        .context("Failed to read the clip")?;
+
+    storage
+        .write_with(&clip_id, content)
+        .content_type("video/mp4")
+        .await
+        .context("Failed to write the clip to the storage")?;
+
+    Ok(clip_id)
+}
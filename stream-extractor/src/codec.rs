@@ -0,0 +1,167 @@
+use anyhow::Context;
+use gst::prelude::*;
+use gstreamer::{self as gst};
+
+/// The video codec of the stream.
+#[derive(Clone, Copy, Debug, Default, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Codec {
+    /// Negotiate the depayloader, parser and decoder from the caps of the stream.
+    #[default]
+    Auto,
+    H264,
+    H265,
+    Mjpeg,
+}
+
+impl Codec {
+    /// Add the elements that depayload and decode the RTP stream to the pipeline.
+    ///
+    /// The decoded stream is linked to `downstream`, and the parsed (encoded) stream
+    /// to `recorder` if set. Returns the first element of the chain, which accepts the RTP stream.
+    pub fn build_decoder(
+        self,
+        pipeline: &gst::Pipeline,
+        downstream: &gst::Element,
+        recorder: Option<&gst::Element>,
+    ) -> anyhow::Result<gst::Element> {
+        let (depay, parse, decoder) = match self {
+            Self::Auto => return build_auto_decoder(pipeline, downstream, recorder),
+            Self::H264 => ("rtph264depay", "h264parse", "avdec_h264"),
+            Self::H265 => ("rtph265depay", "h265parse", "avdec_h265"),
+            Self::Mjpeg => ("rtpjpegdepay", "jpegparse", "jpegdec"),
+        };
+
+        let rtpjitterbuffer_element = gst::ElementFactory::make("rtpjitterbuffer")
+            .build()
+            .context("failed to create rtpjitterbuffer element")?;
+
+        let depay_element = {
+            let builder = gst::ElementFactory::make(depay);
+            match self {
+                Self::H264 | Self::H265 => builder
+                    .property("wait-for-keyframe", true)
+                    .property("request-keyframe", true),
+                _ => builder,
+            }
+        }
+        .build()
+        .with_context(|| format!("failed to create {depay} element"))?;
+
+        let parse_element = gst::ElementFactory::make(parse)
+            .build()
+            .with_context(|| format!("failed to create {parse} element"))?;
+
+        let decoder_element = gst::ElementFactory::make(decoder)
+            .build()
+            .with_context(|| format!("failed to create {decoder} element"))?;
+
+        pipeline.add_many([
+            &rtpjitterbuffer_element,
+            &depay_element,
+            &parse_element,
+            &decoder_element,
+        ])?;
+
+        decoder_element.link(downstream)?;
+        let parsed_element = build_recorder_tee(pipeline, &decoder_element, recorder)?;
+
+        gst::Element::link_many([
+            &rtpjitterbuffer_element,
+            &depay_element,
+            &parse_element,
+            &parsed_element,
+        ])?;
+
+        Ok(rtpjitterbuffer_element)
+    }
+}
+
+/// Build a `parsebin ! decodebin` chain that plugs the elements
+/// according to the caps of the stream.
+fn build_auto_decoder(
+    pipeline: &gst::Pipeline,
+    downstream: &gst::Element,
+    recorder: Option<&gst::Element>,
+) -> anyhow::Result<gst::Element> {
+    let parsebin_element = gst::ElementFactory::make("parsebin")
+        .build()
+        .context("failed to create parsebin element")?;
+
+    let decodebin_element = gst::ElementFactory::make("decodebin")
+        .build()
+        .context("failed to create decodebin element")?;
+
+    pipeline.add_many([&parsebin_element, &decodebin_element])?;
+
+    let parsed_element = build_recorder_tee(pipeline, &decodebin_element, recorder)?;
+    parsebin_element.connect_pad_added(move |_, src_pad| {
+        link_video_pad(src_pad, &parsed_element);
+    });
+
+    let downstream = downstream.clone();
+    decodebin_element.connect_pad_added(move |_, src_pad| {
+        link_video_pad(src_pad, &downstream);
+    });
+
+    Ok(parsebin_element)
+}
+
+/// Split the parsed stream to the decoder and the recorder with a `tee`.
+///
+/// Returns the element that accepts the parsed stream, which is the decoder itself
+/// if there is no recorder.
+fn build_recorder_tee(
+    pipeline: &gst::Pipeline,
+    decoder: &gst::Element,
+    recorder: Option<&gst::Element>,
+) -> anyhow::Result<gst::Element> {
+    let Some(recorder) = recorder else {
+        return Ok(decoder.clone());
+    };
+
+    let tee_element = gst::ElementFactory::make("tee")
+        .build()
+        .context("failed to create tee element")?;
+
+    let queue_element = gst::ElementFactory::make("queue")
+        .build()
+        .context("failed to create queue element")?;
+
+    pipeline.add_many([&tee_element, &queue_element])?;
+    gst::Element::link_many([&tee_element, &queue_element, decoder])?;
+    tee_element.link(recorder)?;
+
+    Ok(tee_element)
+}
+
+/// Check if the pad carries a video stream, either raw, encoded, or in RTP packets.
+pub fn is_video_pad(pad: &gst::Pad) -> bool {
+    let caps = pad.current_caps().unwrap_or_else(|| pad.query_caps(None));
+
+    caps.structure(0).is_some_and(|structure| {
+        structure.name().starts_with("video/")
+            || structure.name().starts_with("image/")
+            || structure
+                .get::<&str>("media")
+                .is_ok_and(|media| media == "video")
+    })
+}
+
+/// Link a dynamic pad to the sink pad of the `element` if it is a video pad.
+///
+/// The other streams (e.g. audio) are left unlinked.
+pub fn link_video_pad(src_pad: &gst::Pad, element: &gst::Element) {
+    if !is_video_pad(src_pad) {
+        tracing::debug!("Ignoring non-video pad {}", src_pad.name());
+        return;
+    }
+
+    let sink_pad = element.static_pad("sink").unwrap();
+    if !sink_pad.is_linked() {
+        match src_pad.link(&sink_pad) {
+            Ok(_) => tracing::info!("Successfully linked pads"),
+            Err(err) => tracing::warn!("Failed to link pads: {:?}", err),
+        }
+    }
+}
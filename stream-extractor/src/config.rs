@@ -1,30 +1,245 @@
+use std::collections::{HashMap, HashSet};
+
 use anyhow::Context;
+use config::{Environment, File, FileFormat, builder::DefaultState};
+use dotenvy::vars;
+use opendal::services::S3Config;
+
+use crate::{
+    codec::Codec,
+    encoding::Encoding,
+    jetstream::JetStreamConfig,
+    mask::MaskConfig,
+    motion::MotionConfig,
+    queue::QueueConfig,
+    reconnect::ReconnectConfig,
+    recorder::RecordingConfig,
+    sampler::Sampling,
+    server::ServerConfig,
+    source::{Pace, Source},
+    spool::SpoolConfig,
+    tamper::TamperConfig,
+};
 
+#[derive(serde::Deserialize)]
 pub struct ExtractorConfig {
-    pub rtsp_url: String,
-    pub monitor_id: Option<String>,
     pub nats_url: String,
-    pub frame_interval: Option<usize>,
+    pub monitors: Vec<MonitorConfig>,
+    #[serde(default)]
+    pub reconnect: ReconnectConfig,
+    /// Publish the frames to JetStream instead of core NATS if set.
+    pub jetstream: Option<JetStreamConfig>,
+    /// The HTTP server of the health checks and the metrics.
+    #[serde(default)]
+    pub server: ServerConfig,
+    /// The object storage of the clips. Required if any monitor is recorded.
+    pub s3: Option<S3Config>,
+    /// Spool the frames on the local disk while NATS is unreachable if set.
+    pub spool: Option<SpoolConfig>,
+    /// How long to wait for the queued frames to be published on shutdown, in seconds.
+    #[serde(default = "default_shutdown_timeout_secs")]
+    pub shutdown_timeout_secs: u64,
+    /// How often the monitors are announced to `monitors.announce`, in seconds.
+    #[serde(default = "default_announce_interval_secs")]
+    pub announce_interval_secs: u64,
+    /// How often the status of the monitors is published to `monitors.status.<monitor_id>`, in seconds.
+    #[serde(default = "default_heartbeat_interval_secs")]
+    pub heartbeat_interval_secs: u64,
+}
+
+fn default_shutdown_timeout_secs() -> u64 {
+    10
+}
+
+fn default_announce_interval_secs() -> u64 {
+    60
+}
+
+fn default_heartbeat_interval_secs() -> u64 {
+    10
+}
+
+/// The configuration of a monitor (camera) to extract frames from.
+#[derive(Clone, serde::Deserialize)]
+pub struct MonitorConfig {
+    /// The ID of the monitor.
+    ///
+    /// It is attached to every frame as the `Monitor-Id` header,
+    /// so it should be unique across all extractors.
+    pub id: String,
+
+    /// The display name of the monitor, announced to the gateway.
+    pub name: Option<String>,
+
+    /// Where the monitor is, announced to the gateway.
+    pub location: Option<String>,
+
+    /// The URL of the stream.
+    ///
+    /// See [`Source`] for the supported schemes.
+    pub url: Source,
+
+    /// See [`crate::worker::ExtractorWorkerBuilder::codec`].
+    #[serde(default)]
+    pub codec: Codec,
+
+    /// See [`crate::worker::ExtractorWorkerBuilder::pace`].
+    #[serde(default)]
+    pub pace: Pace,
+
+    /// See [`crate::worker::ExtractorWorkerBuilder::sampling`].
+    #[serde(default)]
+    pub sampling: Sampling,
+
+    /// See [`crate::worker::ExtractorWorkerBuilder::motion`].
+    pub motion: Option<MotionConfig>,
+
+    /// Detect the camera being covered, defocused, moved or blacked out if set.
+    pub tamper: Option<TamperConfig>,
+
+    /// See [`crate::worker::ExtractorWorkerBuilder::max_width`].
+    pub max_width: Option<u32>,
+
+    /// See [`crate::worker::ExtractorWorkerBuilder::max_height`].
+    pub max_height: Option<u32>,
+
+    /// The format of the frames published to NATS.
+    #[serde(default)]
+    pub encoding: Encoding,
+
+    /// The queue of the frames waiting to be published to NATS.
+    #[serde(default)]
+    pub queue: QueueConfig,
+
+    /// Record the encoded stream to export the clips around the detections if set.
+    pub recording: Option<RecordingConfig>,
+
+    /// The regions masked for privacy before the frames leave the extractor.
+    #[serde(default)]
+    pub masks: Vec<MaskConfig>,
 }
 
 pub fn parse_config() -> anyhow::Result<ExtractorConfig> {
-    let rtsp_url = std::env::var("EXTRACTOR_RTSP_URL").context("EXTRACTOR_RTSP_URL environment variable is not set. RTSP stream is required to receive the frames.")?;
-    let monitor_id = std::env::var("EXTRACTOR_MONITOR_ID").ok();
-    let nats_url = std::env::var("EXTRACTOR_NATS_URL").context("EXTRACTOR_NATS_URL environment variable is not set. NATS is required for the extractor to send the extracted frame.")?;
-    let frame_interval = std::env::var("EXTRACTOR_FRAME_INTERVAL")
-        .ok()
-        .map(|v| v.parse().unwrap());
-
-    if let None = monitor_id {
-        tracing::warn!(
-            "Monitor ID not set. The extractor will omit the monitor ID in messages. It's recommended to set a monitor ID to help users identify the frame source."
+    let dotenv_variables = HashMap::from_iter(vars());
+
+    let config = config::ConfigBuilder::<DefaultState>::default()
+        .add_source(
+            Environment::default()
+                .prefix("IOT")
+                .prefix_separator("_")
+                .keep_prefix(false)
+                .separator("__"),
+        )
+        .add_source(
+            Environment::default()
+                .source(Some(dotenv_variables))
+                .separator("__"),
+        )
+        .add_source(File::new("config.toml", FileFormat::Toml).required(false))
+        .build()
+        .context("Failed to build configuration")?;
+
+    let deserialized_config: ExtractorConfig = config
+        .try_deserialize()
+        .context("Failed to deserialize configuration")?;
+
+    if deserialized_config.monitors.is_empty() {
+        anyhow::bail!(
+            "No monitors configured. Add at least one [[monitors]] entry to config.toml to receive the frames."
         );
     }
 
-    Ok(ExtractorConfig {
-        rtsp_url,
-        monitor_id,
-        nats_url,
-        frame_interval,
-    })
+    if deserialized_config.announce_interval_secs == 0 {
+        anyhow::bail!("announce_interval_secs should be greater than 0.");
+    }
+    if deserialized_config.heartbeat_interval_secs == 0 {
+        anyhow::bail!("heartbeat_interval_secs should be greater than 0.");
+    }
+
+    if let Some(spool) = &deserialized_config.spool {
+        spool.validate().context("Invalid spool")?;
+    }
+
+    let mut monitor_ids = HashSet::new();
+    for monitor in &deserialized_config.monitors {
+        if !monitor_ids.insert(monitor.id.as_str()) {
+            anyhow::bail!("Duplicated monitor ID: {}", monitor.id);
+        }
+
+        if !matches!(monitor.codec, Codec::Auto) && !monitor.url.supports_codec_override() {
+            anyhow::bail!(
+                "The codec of monitor {} cannot be overridden: only RTSP sources support it.",
+                monitor.id
+            );
+        }
+
+        if monitor.max_width == Some(0) || monitor.max_height == Some(0) {
+            anyhow::bail!(
+                "The maximum resolution of monitor {} should be greater than 0.",
+                monitor.id
+            );
+        }
+
+        monitor
+            .encoding
+            .validate()
+            .with_context(|| format!("Invalid encoding of monitor {}", monitor.id))?;
+
+        if monitor.queue.capacity == 0 {
+            anyhow::bail!(
+                "The queue capacity of monitor {} should be greater than 0.",
+                monitor.id
+            );
+        }
+
+        monitor
+            .sampling
+            .validate()
+            .with_context(|| format!("Invalid sampling of monitor {}", monitor.id))?;
+
+        if let Some(motion) = &monitor.motion {
+            motion
+                .validate()
+                .with_context(|| format!("Invalid motion gate of monitor {}", monitor.id))?;
+        }
+
+        if let Some(tamper) = &monitor.tamper {
+            tamper
+                .validate()
+                .with_context(|| format!("Invalid tamper detection of monitor {}", monitor.id))?;
+        }
+
+        for (index, mask) in monitor.masks.iter().enumerate() {
+            mask.validate()
+                .with_context(|| format!("Invalid mask #{index} of monitor {}", monitor.id))?;
+        }
+
+        if let Some(recording) = &monitor.recording {
+            // The recording is the original stream, which cannot be masked without encoding it again.
+            if !monitor.masks.is_empty() {
+                anyhow::bail!(
+                    "Monitor {} cannot be recorded: the recording would contain the masked regions.",
+                    monitor.id
+                );
+            }
+            if !monitor.url.supports_recording() {
+                anyhow::bail!(
+                    "Monitor {} cannot be recorded: only RTSP and HTTP sources support it.",
+                    monitor.id
+                );
+            }
+            if deserialized_config.s3.is_none() {
+                anyhow::bail!(
+                    "Monitor {} is recorded, but no [s3] storage is configured for the clips.",
+                    monitor.id
+                );
+            }
+
+            recording
+                .validate()
+                .with_context(|| format!("Invalid recording of monitor {}", monitor.id))?;
+        }
+    }
+
+    Ok(deserialized_config)
 }
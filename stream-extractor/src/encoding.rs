@@ -0,0 +1,94 @@
+use anyhow::Context;
+use bytes::Bytes;
+use image::{DynamicImage, ImageEncoder, codecs::jpeg::JpegEncoder, codecs::png::PngEncoder};
+
+/// The format of the frames published to NATS.
+///
+/// It trades the bandwidth against the CPU time of the extractor and the recognition worker.
+#[derive(Clone, Copy, Debug, serde::Deserialize)]
+#[serde(tag = "format", rename_all = "snake_case")]
+pub enum Encoding {
+    Jpeg {
+        /// The quality of the image, from 1 to 100.
+        #[serde(default = "default_quality")]
+        quality: u8,
+    },
+    Png,
+    Webp {
+        /// Encode the image losslessly. `quality` is ignored if it is set.
+        #[serde(default)]
+        lossless: bool,
+        /// The quality of the lossy image, from 0 to 100.
+        #[serde(default = "default_quality")]
+        quality: u8,
+    },
+}
+
+fn default_quality() -> u8 {
+    80
+}
+
+impl Default for Encoding {
+    fn default() -> Self {
+        Self::Webp {
+            lossless: true,
+            quality: default_quality(),
+        }
+    }
+}
+
+impl Encoding {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        match *self {
+            Self::Jpeg { quality } if !(1..=100).contains(&quality) => {
+                anyhow::bail!("the quality of JPEG should be in the range of 1 to 100")
+            }
+            Self::Webp { quality, .. } if quality > 100 => {
+                anyhow::bail!("the quality of WebP should be in the range of 0 to 100")
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// The MIME type of the encoded image, used as the `Content-Type` header.
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Self::Jpeg { .. } => "image/jpeg",
+            Self::Png => "image/png",
+            Self::Webp { .. } => "image/webp",
+        }
+    }
+
+    /// Encode the image.
+    pub fn encode(&self, image: &DynamicImage) -> anyhow::Result<Bytes> {
+        let image = image.to_rgb8();
+        let (width, height) = image.dimensions();
+
+        let buf = match *self {
+            Self::Jpeg { quality } => {
+                let mut buf = Vec::new();
+                JpegEncoder::new_with_quality(&mut buf, quality)
+                    .write_image(&image, width, height, image::ExtendedColorType::Rgb8)
+                    .context("Failed to encode frame to JPEG")?;
+                buf
+            }
+            Self::Png => {
+                let mut buf = Vec::new();
+                PngEncoder::new(&mut buf)
+                    .write_image(&image, width, height, image::ExtendedColorType::Rgb8)
+                    .context("Failed to encode frame to PNG")?;
+                buf
+            }
+            Self::Webp { lossless, quality } => {
+                // the compression effort of the lossless encoding, as in `encode_lossless`
+                let quality = if lossless { 75.0 } else { quality as f32 };
+                webp::Encoder::from_rgb(&image, width, height)
+                    .encode_simple(lossless, quality)
+                    .map_err(|e| anyhow::anyhow!("Failed to encode frame to WebP: {e:?}"))?
+                    .to_vec()
+            }
+        };
+
+        Ok(Bytes::from(buf))
+    }
+}
@@ -0,0 +1,76 @@
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use tokio_util::sync::CancellationToken;
+
+use crate::metrics::{MonitorMetrics, PipelineState};
+
+/// The heartbeat of a monitor, published to `monitors.status.<monitor_id>`.
+#[derive(Debug, serde::Serialize)]
+struct MonitorHeartbeat<'a> {
+    monitor_id: &'a str,
+    state: PipelineState,
+
+    /// When the pipeline entered the current state.
+    state_since: DateTime<Utc>,
+
+    /// When the last frame was decoded, or `None` if no frame has been decoded yet.
+    last_frame_at: Option<DateTime<Utc>>,
+
+    sent_at: DateTime<Utc>,
+}
+
+/// Publish the status of a monitor every `interval` until the shutdown.
+pub async fn send_heartbeats(
+    client: async_nats::Client,
+    monitor_id: String,
+    metrics: MonitorMetrics,
+    interval: Duration,
+    shutdown: CancellationToken,
+) {
+    let subject = format!("monitors.status.{monitor_id}");
+    let mut ticker = tokio::time::interval(interval);
+
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {}
+            _ = shutdown.cancelled() => break,
+        }
+
+        let status = metrics.status();
+        let now = Instant::now();
+        let sent_at = Utc::now();
+        let heartbeat = MonitorHeartbeat {
+            monitor_id: &monitor_id,
+            state: status.state,
+            state_since: wall_clock(now, sent_at, status.state_since),
+            last_frame_at: status.last_frame_at.map(|at| wall_clock(now, sent_at, at)),
+            sent_at,
+        };
+
+        if let Err(e) = publish_heartbeat(&client, &subject, &heartbeat).await {
+            tracing::warn!("Failed to send the heartbeat of {monitor_id}: {:?}", e);
+        }
+    }
+}
+
+/// Convert an [`Instant`] to the wall clock, given the wall clock at `now`.
+fn wall_clock(now: Instant, now_utc: DateTime<Utc>, at: Instant) -> DateTime<Utc> {
+    now_utc - now.saturating_duration_since(at)
+}
+
+async fn publish_heartbeat(
+    client: &async_nats::Client,
+    subject: &str,
+    heartbeat: &MonitorHeartbeat<'_>,
+) -> anyhow::Result<()> {
+    let payload = serde_json::to_vec(heartbeat).context("Failed to serialize the heartbeat")?;
+
+    client
+        .publish(subject.to_string(), payload.into())
+        .await
+        .context("Failed to publish the heartbeat")?;
+
+    Ok(())
+}
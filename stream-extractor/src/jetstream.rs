@@ -0,0 +1,47 @@
+use std::time::Duration;
+
+use async_nats::jetstream::stream::RetentionPolicy;
+use iot_common::jetstream::PublishedStream;
+
+/// The configuration of the JetStream mode.
+///
+/// In this mode, the frames are published to a JetStream stream, so they
+/// are kept while the recognition workers restart.
+#[derive(Clone, Debug, serde::Deserialize)]
+#[serde(default)]
+pub struct JetStreamConfig {
+    /// The name of the stream of the frames.
+    pub stream: String,
+
+    /// The retention policy of the stream: `limits`, `interest` or `workqueue`.
+    pub retention: RetentionPolicy,
+
+    /// The maximum age of the frames in the stream, in seconds.
+    pub max_age_secs: u64,
+
+    /// The maximum size of the stream, in bytes. Unlimited if not set.
+    pub max_bytes: Option<i64>,
+}
+
+impl Default for JetStreamConfig {
+    fn default() -> Self {
+        Self {
+            stream: "FRAMES".to_string(),
+            retention: RetentionPolicy::WorkQueue,
+            max_age_secs: 600,
+            max_bytes: None,
+        }
+    }
+}
+
+impl JetStreamConfig {
+    /// The stream of the frames.
+    pub fn stream(&self) -> PublishedStream<'_> {
+        PublishedStream {
+            name: &self.stream,
+            retention: self.retention,
+            max_age: Duration::from_secs(self.max_age_secs),
+            max_bytes: self.max_bytes,
+        }
+    }
+}
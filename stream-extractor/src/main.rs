@@ -1,16 +1,51 @@
+pub(crate) mod announce;
+pub(crate) mod clip;
+pub(crate) mod codec;
 pub(crate) mod config;
+pub(crate) mod encoding;
+pub(crate) mod heartbeat;
+pub(crate) mod jetstream;
+pub(crate) mod mask;
+pub(crate) mod metrics;
+pub(crate) mod motion;
+pub(crate) mod queue;
+pub(crate) mod reconnect;
+pub(crate) mod recorder;
+pub(crate) mod sampler;
+pub(crate) mod server;
+pub(crate) mod snapshot;
+pub(crate) mod source;
+pub(crate) mod spool;
+pub(crate) mod tamper;
 pub(crate) mod worker;
 
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use announce::StreamInfo;
 use anyhow::Context;
 use async_nats::HeaderMap;
 use bytes::Bytes;
-use config::ExtractorConfig;
-use crossbeam::channel::bounded;
+use config::{ExtractorConfig, MonitorConfig};
+use encoding::Encoding;
 use gst::prelude::*;
 use gstreamer::prelude::ElementExt;
 use gstreamer::{self as gst};
-use image::{DynamicImage, ImageFormat};
-use tokio_util::task::TaskTracker;
+use iot_common::jetstream::Publisher;
+use iot_common::shutdown::shutdown_signal;
+use jetstream::JetStreamConfig;
+use mask::PrivacyMasks;
+use metrics::{DropReason, Metrics, MonitorMetrics, PipelineState};
+use queue::FrameSender;
+use reconnect::{Backoff, ReconnectConfig};
+use recorder::Recorder;
+use spool::Spool;
+use tamper::{TamperDetector, TamperEvent};
+use tokio::sync::{mpsc, watch};
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
+use worker::{ExtractedFrame, LatestFrame};
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
@@ -20,104 +55,531 @@ async fn main() -> anyhow::Result<()> {
     gst::init()?;
 
     let ExtractorConfig {
-        rtsp_url,
-        monitor_id,
         nats_url,
-        frame_interval,
+        monitors,
+        reconnect,
+        jetstream,
+        server,
+        s3,
+        spool,
+        shutdown_timeout_secs,
+        announce_interval_secs,
+        heartbeat_interval_secs,
     } = config::parse_config()?;
 
-    let (sender, receiver) = bounded::<(usize, DynamicImage)>(30); // 30s
-
     let nats_client = async_nats::connect(&nats_url)
         .await
         .context("Failed to connect to NATS")?;
 
+    let publisher = Publisher::new(
+        nats_client.clone(),
+        "frames",
+        jetstream.as_ref().map(JetStreamConfig::stream),
+    )
+    .await?;
+
+    let metrics = Arc::new(Metrics::new()?);
+
+    let storage = s3
+        .map(clip::build_storage)
+        .transpose()
+        .context("Failed to build storage")?
+        .map(Arc::new);
+
     let task_tracker = TaskTracker::new();
+    // The services run until the shutdown, so they are tracked apart from the workers,
+    // whose end means all the monitors are played to the end.
+    let service_tracker = TaskTracker::new();
+    let runtime = tokio::runtime::Handle::current();
+    let shutdown = CancellationToken::new();
 
-    let extractor_worker_thread = task_tracker.spawn_blocking::<_, anyhow::Result<()>>(move || {
-        let extractor_worker = worker::ExtractorWorkerBuilder {
-            rtsp_url,
-            sender,
-            frame_interval,
+    let spool = match spool {
+        Some(spool) => {
+            let spool = Arc::new(
+                Spool::open(spool, nats_client.clone(), metrics.clone())
+                    .context("Failed to open the spool")?,
+            );
+            service_tracker.spawn({
+                let spool = spool.clone();
+                let publisher = publisher.clone();
+                let shutdown = shutdown.clone();
+                async move { spool.replay(publisher, shutdown).await }
+            });
+            Some(spool)
         }
-        .build()
-        .context("Failed to build extractor worker")?;
-
-        extractor_worker
-            .set_state(gst::State::Playing)
-            .context("failed to start extractor worker")?;
-
-        // Wait until error or EOS ()
-        let bus = extractor_worker.bus().context("failed to get bus")?;
-        for msg in bus.iter_timed(gst::ClockTime::from_seconds(30)) {
-            match msg.view() {
-                gst::MessageView::Eos(..) => {
-                    tracing::info!("End of stream. You should restart the worker manaully if there is a new stream.");
-                    break;
-                }
-                gst::MessageView::Error(err) => {
-                    tracing::error!(
-                        "Error from {}: {}",
-                        err.src().map(|s| s.path_string()).unwrap_or("<?>".into()),
-                        err.error()
-                    );
-                    break;
+        None => None,
+    };
+
+    for monitor in monitors {
+        tracing::info!("Starting extractor for monitor {}", monitor.id);
+
+        let monitor_metrics = metrics.monitor(&monitor.id);
+        let overflow = monitor.queue.overflow_policy(&monitor.url, monitor.pace);
+        let (sender, receiver) =
+            queue::frame_queue(&monitor.queue, overflow, monitor_metrics.clone());
+
+        let monitor_id = monitor.id.clone();
+        let encoding = monitor.encoding;
+        let reconnect = reconnect.clone();
+        let masks = PrivacyMasks::new(monitor.masks.clone());
+        let latest_frame = LatestFrame::new(masks.clone());
+
+        // Cancelled once the extractor worker stops, or on the shutdown.
+        let worker_stopped = shutdown.child_token();
+
+        // The snapshot service runs until the extractor worker stops. It holds a sender
+        // of the queue, so the forwarder ends once it stops as well.
+        service_tracker.spawn({
+            let nats_client = nats_client.clone();
+            let monitor_id = monitor_id.clone();
+            let latest_frame = latest_frame.clone();
+            let sender = sender.clone();
+            let shutdown = worker_stopped.clone();
+            async move {
+                if let Err(e) = snapshot::serve_snapshots(
+                    nats_client,
+                    monitor_id,
+                    encoding,
+                    latest_frame,
+                    sender,
+                    shutdown,
+                )
+                .await
+                {
+                    tracing::error!("The snapshot service stopped: {:?}", e);
                 }
-                _ => (),
             }
+        });
+
+        let recorder = match &monitor.recording {
+            Some(recording) => Some(
+                Recorder::new(&monitor.id, recording.clone())
+                    .with_context(|| format!("Failed to set up recording of {}", monitor.id))?,
+            ),
+            None => None,
+        };
+
+        if let (Some(recorder), Some(storage)) = (&recorder, &storage) {
+            let nats_client = nats_client.clone();
+            let storage = storage.clone();
+            let monitor_id = monitor_id.clone();
+            let recorder = recorder.clone();
+            let tracker = service_tracker.clone();
+            let shutdown = shutdown.clone();
+            service_tracker.spawn(async move {
+                if let Err(e) = clip::serve_clips(
+                    nats_client,
+                    storage,
+                    monitor_id,
+                    recorder,
+                    tracker,
+                    shutdown,
+                )
+                .await
+                {
+                    tracing::error!("The clip service stopped: {:?}", e);
+                }
+            });
         }
 
-        extractor_worker
-            .set_state(gst::State::Null)
-            .context("failed to stop extractor worker")?;
+        let (stream_info, stream_info_receiver) = watch::channel(StreamInfo::default());
+        service_tracker.spawn(announce::announce_monitor(
+            nats_client.clone(),
+            monitor_id.clone(),
+            monitor.name.clone(),
+            monitor.location.clone(),
+            stream_info_receiver,
+            Duration::from_secs(announce_interval_secs),
+            shutdown.clone(),
+        ));
 
-        Ok(())
-    });
+        service_tracker.spawn(heartbeat::send_heartbeats(
+            nats_client.clone(),
+            monitor_id.clone(),
+            monitor_metrics.clone(),
+            Duration::from_secs(heartbeat_interval_secs),
+            shutdown.clone(),
+        ));
+
+        // The tamper events are published until the worker stops and drops the detector.
+        let tamper = monitor.tamper.clone().map(|config| {
+            let (events, receiver) = mpsc::channel(16);
+            task_tracker.spawn(publish_tamper_events(
+                nats_client.clone(),
+                monitor_id.clone(),
+                encoding,
+                receiver,
+            ));
+            TamperDetector::new(config, events)
+        });
 
-    for (frame_id, frame) in receiver {
-        tracing::info!("Received frame {frame_id} from monitor {monitor_id:?}; sending to NATS");
+        let context = MonitorContext {
+            sender,
+            masks,
+            metrics: monitor_metrics.clone(),
+            latest_frame,
+            recorder,
+            tamper,
+            stream_info,
+            shutdown: shutdown.clone(),
+        };
+        task_tracker.spawn_blocking(move || {
+            run_extractor_worker(monitor, reconnect, context);
+            worker_stopped.cancel();
+        });
+
+        // Forward the frames of this monitor to NATS one at a time, so a slow
+        // NATS fills the queue and its overflow policy applies. It ends once the
+        // extractor worker stops and drops the sender.
+        let publisher = publisher.clone();
+        let spool = spool.clone();
+        let runtime = runtime.clone();
+        task_tracker.spawn_blocking(move || {
+            for frame in receiver {
+                tracing::info!(
+                    "Received frame {} from monitor {monitor_id}; sending to NATS",
+                    frame.id
+                );
+
+                runtime.block_on(publish_frame(
+                    publisher.clone(),
+                    spool.clone(),
+                    monitor_id.clone(),
+                    encoding,
+                    frame,
+                    monitor_metrics.clone(),
+                ));
+            }
+        });
+    }
 
+    service_tracker.spawn({
         let nats_client = nats_client.clone();
-        let monitor_id = monitor_id.clone();
+        let shutdown = shutdown.clone();
+        async move {
+            if let Err(e) = server::serve(server, metrics, nats_client, shutdown).await {
+                tracing::error!("The HTTP server stopped: {:?}", e);
+            }
+        }
+    });
 
-        task_tracker.spawn(async move {
-            let mut buf = Vec::new();
-            let mut cursor = std::io::Cursor::new(&mut buf);
+    task_tracker.close();
+    service_tracker.close();
+
+    tokio::select! {
+        // all the monitors are files, and they are played to the end
+        _ = task_tracker.wait() => {}
+        _ = shutdown_signal() => {}
+    }
+
+    // Stop the pipelines and the services, and wait for the queued frames to be published.
+    shutdown.cancel();
+
+    let shutdown_timeout = Duration::from_secs(shutdown_timeout_secs);
+    let stopped = async {
+        task_tracker.wait().await;
+        service_tracker.wait().await;
+    };
+    if tokio::time::timeout(shutdown_timeout, stopped)
+        .await
+        .is_err()
+    {
+        tracing::warn!(
+            "Timed out waiting for {} tasks to finish; dropping them.",
+            task_tracker.len() + service_tracker.len()
+        );
+    }
+
+    if let Err(e) = nats_client.flush().await {
+        tracing::error!("Failed to flush the NATS client: {:?}", e);
+    }
+
+    tracing::info!("Shut down.");
+
+    Ok(())
+}
+
+/// How long to wait for the pipeline to drain on shutdown.
+const EOS_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How often the pipeline checks for the shutdown.
+const BUS_POLL_INTERVAL: gst::ClockTime = gst::ClockTime::from_mseconds(100);
+
+/// The state of a monitor shared across the runs of its pipeline.
+#[derive(Clone)]
+struct MonitorContext {
+    sender: FrameSender,
+    masks: PrivacyMasks,
+    metrics: MonitorMetrics,
+    latest_frame: LatestFrame,
+    recorder: Option<Recorder>,
+    tamper: Option<TamperDetector>,
+    stream_info: watch::Sender<StreamInfo>,
+    shutdown: CancellationToken,
+}
+
+/// Run the extractor pipeline of a monitor.
+///
+/// The pipeline is torn down and rebuilt with backoff whenever it fails
+/// or the live stream ends, so it never returns while the monitor is configured.
+/// A non-live source (i.e. a file) returns once it is played to the end.
+#[tracing::instrument(skip_all, fields(monitor_id = %monitor.id))]
+fn run_extractor_worker(
+    monitor: MonitorConfig,
+    reconnect: ReconnectConfig,
+    context: MonitorContext,
+) {
+    let metrics = &context.metrics;
+    let mut backoff = Backoff::new(&reconnect);
+    let stable_after = Duration::from_millis(reconnect.stable_after_ms);
+    let mut reconnects = 0u64;
+
+    loop {
+        let started_at = Instant::now();
+        metrics.set_state(PipelineState::Starting);
+
+        let result = run_extractor_pipeline(&monitor, context.clone());
+        if context.shutdown.is_cancelled() {
+            tracing::info!("Stopped extracting frames from {}", monitor.url);
+            metrics.set_state(PipelineState::Finished);
+            return;
+        }
 
-            if let Err(e) = frame.write_to(&mut cursor, ImageFormat::WebP) {
-                tracing::error!("Failed to write frame to WebP: {:?}", e);
+        match result {
+            Ok(()) if !monitor.url.is_live() => {
+                tracing::info!("Finished extracting frames from {}", monitor.url);
+                metrics.set_state(PipelineState::Finished);
                 return;
             }
+            Ok(()) => {}
+            Err(e) => tracing::error!("Extractor worker failed: {:?}", e),
+        }
 
-            let bytes = Bytes::from(buf);
+        if started_at.elapsed() >= stable_after {
+            backoff.reset();
+        }
 
-            let mut nats_header = HeaderMap::new();
-            nats_header.append("Content-Type", "image/webp");
-            nats_header.append("Date", chrono::Utc::now().to_rfc3339());
-            nats_header.append("Frame-Id", frame_id.to_string());
+        metrics.set_state(PipelineState::Reconnecting);
+        metrics.reconnects.inc();
 
-            if let Some(monitor_id) = monitor_id {
-                nats_header.append("Monitor-Id", monitor_id);
-            }
+        let delay = backoff.next_delay();
+        reconnects += 1;
+        tracing::warn!("Reconnecting in {delay:?} (reconnect #{reconnects})");
 
-            // publish the frame to NATS
-            let result = nats_client
-                .publish_with_headers("frames", nats_header, bytes)
-                .await;
-            if let Err(err) = result {
-                tracing::error!("Failed to publish frame to NATS: {:?}", err);
-                return;
+        tokio::runtime::Handle::current().block_on(async {
+            tokio::select! {
+                _ = tokio::time::sleep(delay) => {}
+                _ = context.shutdown.cancelled() => {}
             }
         });
     }
+}
 
-    task_tracker.close();
+/// Run the extractor pipeline once, until the stream fails or ends.
+///
+/// Returns an error if the pipeline fails.
+fn run_extractor_pipeline(monitor: &MonitorConfig, context: MonitorContext) -> anyhow::Result<()> {
+    let MonitorContext {
+        sender,
+        masks,
+        metrics,
+        latest_frame,
+        recorder,
+        tamper,
+        stream_info,
+        shutdown,
+    } = context;
 
-    if let Err(e) = extractor_worker_thread.await {
-        tracing::error!("Extractor worker thread failed: {:?}", e);
+    let extractor_worker = worker::ExtractorWorkerBuilder {
+        source: monitor.url.clone(),
+        codec: monitor.codec,
+        pace: monitor.pace,
+        sender,
+        sampling: monitor.sampling,
+        motion: monitor.motion.clone(),
+        max_width: monitor.max_width,
+        max_height: monitor.max_height,
+        masks,
+        metrics: metrics.clone(),
+        latest_frame,
+        recorder: recorder.clone(),
+        tamper,
+        stream_info: stream_info.clone(),
     }
+    .build()
+    .context("Failed to build extractor worker")?;
 
-    task_tracker.wait().await;
+    extractor_worker
+        .set_state(gst::State::Playing)
+        .context("failed to start extractor worker")?;
 
-    Ok(())
+    // Wait until error or EOS
+    let mut result = Ok(());
+    let mut eos_deadline = None;
+    let bus = extractor_worker.bus().context("failed to get bus")?;
+    loop {
+        if eos_deadline.is_none() && shutdown.is_cancelled() {
+            // Send EOS so that the elements finalize their outputs (e.g. the recorded segment).
+            tracing::info!("Stopping the pipeline.");
+            extractor_worker.send_event(gst::event::Eos::new());
+            eos_deadline = Some(Instant::now() + EOS_TIMEOUT);
+        }
+        if eos_deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            tracing::warn!("Timed out waiting for the pipeline to drain.");
+            break;
+        }
+
+        let Some(msg) = bus.timed_pop(BUS_POLL_INTERVAL) else {
+            continue;
+        };
+
+        if let Some(recorder) = &recorder {
+            recorder.handle_message(&msg, extractor_worker.base_time());
+        }
+
+        match msg.view() {
+            gst::MessageView::Tag(tag) => {
+                stream_info.send_if_modified(|info| info.update_from_tags(&tag.tags()));
+            }
+            gst::MessageView::Eos(..) => {
+                tracing::info!("End of stream.");
+                break;
+            }
+            gst::MessageView::StateChanged(state_changed)
+                if state_changed.src() == Some(extractor_worker.upcast_ref())
+                    && state_changed.current() == gst::State::Playing =>
+            {
+                metrics.set_state(PipelineState::Playing);
+            }
+            gst::MessageView::Error(err) => {
+                result = Err(anyhow::anyhow!(
+                    "Error from {}: {}",
+                    err.src().map(|s| s.path_string()).unwrap_or("<?>".into()),
+                    err.error()
+                ));
+                break;
+            }
+            _ => (),
+        }
+    }
+
+    extractor_worker
+        .set_state(gst::State::Null)
+        .context("failed to stop extractor worker")?;
+
+    result
+}
+
+async fn publish_frame(
+    publisher: Publisher,
+    spool: Option<Arc<Spool>>,
+    monitor_id: String,
+    encoding: Encoding,
+    frame: ExtractedFrame,
+    metrics: MonitorMetrics,
+) {
+    let mut nats_header = frame_headers(&monitor_id, encoding, &frame);
+    nats_header.append("Published-At", chrono::Utc::now().to_rfc3339());
+
+    let bytes = match tokio::task::spawn_blocking(move || encoding.encode(&frame.image)).await {
+        Ok(Ok(bytes)) => bytes,
+        Ok(Err(e)) => {
+            tracing::error!("Failed to encode frame: {:?}", e);
+            metrics.record_dropped(DropReason::EncodeFailed);
+            return;
+        }
+        Err(e) => {
+            tracing::error!("Failed to create a thread to encode the frame: {:?}", e);
+            metrics.record_dropped(DropReason::EncodeFailed);
+            return;
+        }
+    };
+    metrics.frames_encoded.inc();
+
+    if let Some(spool) = &spool
+        && spool.should_spool().await
+    {
+        spool_frame(spool, &nats_header, &bytes, &metrics).await;
+        return;
+    }
+
+    // publish the frame to NATS
+    let publish_timer = metrics.publish_latency.start_timer();
+    let result = publisher
+        .publish("frames", nats_header.clone(), bytes.clone())
+        .await;
+    match result {
+        Ok(()) => {
+            publish_timer.observe_duration();
+            metrics.frames_published.inc();
+        }
+        Err(err) => {
+            publish_timer.stop_and_discard();
+            tracing::error!("Failed to publish frame to NATS: {:?}", err);
+            match &spool {
+                Some(spool) => spool_frame(spool, &nats_header, &bytes, &metrics).await,
+                None => {
+                    metrics.record_dropped(DropReason::PublishFailed);
+                }
+            }
+        }
+    }
+}
+
+async fn spool_frame(spool: &Spool, headers: &HeaderMap, bytes: &Bytes, metrics: &MonitorMetrics) {
+    tracing::debug!("Spooling the frame until NATS is reachable.");
+
+    if let Err(err) = spool.push(headers, bytes).await {
+        tracing::error!("Failed to spool the frame: {:?}", err);
+        metrics.record_dropped(DropReason::PublishFailed);
+    }
+}
+
+/// Publish the tamper events of a monitor to `tamper.<monitor_id>`, with the frame
+/// and the same headers as the frames, plus `Tamper-Kind`.
+async fn publish_tamper_events(
+    client: async_nats::Client,
+    monitor_id: String,
+    encoding: Encoding,
+    mut receiver: mpsc::Receiver<TamperEvent>,
+) {
+    let subject = format!("tamper.{monitor_id}");
+
+    while let Some(TamperEvent { kind, frame }) = receiver.recv().await {
+        let image = frame.image.clone();
+        let bytes = match tokio::task::spawn_blocking(move || encoding.encode(&image)).await {
+            Ok(Ok(bytes)) => bytes,
+            Ok(Err(e)) => {
+                tracing::error!("Failed to encode the tamper frame: {:?}", e);
+                continue;
+            }
+            Err(e) => {
+                tracing::error!(
+                    "Failed to create a thread to encode the tamper frame: {:?}. It should not happened :(",
+                    e
+                );
+                continue;
+            }
+        };
+
+        let mut headers = frame_headers(&monitor_id, encoding, &frame);
+        headers.append("Tamper-Kind", kind.as_str());
+
+        if let Err(e) = client
+            .publish_with_headers(subject.clone(), headers, bytes)
+            .await
+        {
+            tracing::error!("Failed to publish the tamper event to NATS: {:?}", e);
+        }
+    }
+}
+
+/// The NATS headers describing a frame.
+pub fn frame_headers(monitor_id: &str, encoding: Encoding, frame: &ExtractedFrame) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.append("Content-Type", encoding.content_type());
+    headers.append("Date", frame.captured_at.to_rfc3339());
+    headers.append("Frame-Id", frame.id.to_string());
+    headers.append("Monitor-Id", monitor_id);
+    headers
 }
@@ -0,0 +1,258 @@
+use std::sync::{Arc, Mutex};
+
+use image::{Rgb, RgbImage, imageops};
+
+/// A region of the frame to be masked for privacy.
+#[derive(Clone, Debug, serde::Deserialize)]
+pub struct MaskConfig {
+    /// The vertices of the polygon, in coordinates normalized to the frame
+    /// from `[0.0, 0.0]` (top left) to `[1.0, 1.0]` (bottom right).
+    pub points: Vec<[f32; 2]>,
+
+    /// How the region is masked.
+    #[serde(default)]
+    pub style: MaskStyle,
+
+    /// The color to fill the region with, for [`MaskStyle::Fill`].
+    #[serde(default)]
+    pub color: [u8; 3],
+
+    /// The standard deviation of the blur in pixels, for [`MaskStyle::Blur`].
+    #[serde(default = "default_blur_sigma")]
+    pub blur_sigma: f32,
+}
+
+fn default_blur_sigma() -> f32 {
+    20.0
+}
+
+/// How a region is masked.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MaskStyle {
+    /// Fill the region with a solid color.
+    #[default]
+    Fill,
+
+    /// Blur the region.
+    Blur,
+}
+
+impl MaskConfig {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.points.len() < 3 {
+            anyhow::bail!("a mask should have at least 3 points");
+        }
+        if self
+            .points
+            .iter()
+            .flatten()
+            .any(|coordinate| !(0.0..=1.0).contains(coordinate))
+        {
+            anyhow::bail!("the points of a mask should be in the range of 0.0 to 1.0");
+        }
+        if self.style == MaskStyle::Blur && !(self.blur_sigma.is_finite() && self.blur_sigma > 0.0)
+        {
+            anyhow::bail!("blur_sigma should be a positive number");
+        }
+
+        Ok(())
+    }
+}
+
+/// The privacy masks of a monitor.
+///
+/// The polygons are rasterized once for each resolution of the frames.
+/// The [`Clone`] operation is cheap.
+#[derive(Clone, Default)]
+pub struct PrivacyMasks {
+    masks: Arc<[MaskConfig]>,
+    rasterized: Arc<Mutex<Option<RasterizedMasks>>>,
+}
+
+struct RasterizedMasks {
+    width: u32,
+    height: u32,
+    regions: Vec<Region>,
+}
+
+/// A mask rasterized to the pixels within its bounding box.
+struct Region {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+    /// Whether each pixel of the bounding box is inside the polygon, row by row.
+    inside: Vec<bool>,
+}
+
+impl PrivacyMasks {
+    pub fn new(masks: Vec<MaskConfig>) -> Self {
+        Self {
+            masks: masks.into(),
+            rasterized: Arc::default(),
+        }
+    }
+
+    /// Mask the regions in the frame.
+    pub fn apply(&self, image: &mut RgbImage) {
+        if self.masks.is_empty() {
+            return;
+        }
+
+        let (width, height) = image.dimensions();
+        let mut rasterized = self.rasterized.lock().unwrap_or_else(|e| e.into_inner());
+        let rasterized = match &mut *rasterized {
+            Some(rasterized) if rasterized.width == width && rasterized.height == height => {
+                rasterized
+            }
+            rasterized => rasterized.insert(RasterizedMasks {
+                width,
+                height,
+                regions: self
+                    .masks
+                    .iter()
+                    .map(|mask| Region::rasterize(&mask.points, width, height))
+                    .collect(),
+            }),
+        };
+
+        for (mask, region) in self.masks.iter().zip(&rasterized.regions) {
+            if region.width == 0 || region.height == 0 {
+                continue;
+            }
+
+            match mask.style {
+                MaskStyle::Fill => region.fill(image, |_, _| Rgb(mask.color)),
+                MaskStyle::Blur => {
+                    let blurred = imageops::fast_blur(
+                        &imageops::crop_imm(image, region.x, region.y, region.width, region.height)
+                            .to_image(),
+                        mask.blur_sigma,
+                    );
+                    region.fill(image, |x, y| *blurred.get_pixel(x, y));
+                }
+            }
+        }
+    }
+}
+
+impl Region {
+    /// Rasterize the polygon with the even-odd rule, sampling at the center of each pixel.
+    fn rasterize(points: &[[f32; 2]], width: u32, height: u32) -> Self {
+        let scaled = points
+            .iter()
+            .map(|[x, y]| (x * width as f32, y * height as f32))
+            .collect::<Vec<_>>();
+
+        let (min_x, max_x, min_y, max_y) = scaled.iter().fold(
+            (f32::MAX, f32::MIN, f32::MAX, f32::MIN),
+            |(min_x, max_x, min_y, max_y), &(x, y)| {
+                (min_x.min(x), max_x.max(x), min_y.min(y), max_y.max(y))
+            },
+        );
+        let x0 = (min_x.floor().max(0.0) as u32).min(width);
+        let y0 = (min_y.floor().max(0.0) as u32).min(height);
+        let x1 = (max_x.ceil().max(0.0) as u32).min(width);
+        let y1 = (max_y.ceil().max(0.0) as u32).min(height);
+
+        let mut inside = Vec::with_capacity(((x1 - x0) * (y1 - y0)) as usize);
+        for y in y0..y1 {
+            let py = y as f32 + 0.5;
+            for x in x0..x1 {
+                let px = x as f32 + 0.5;
+
+                let mut is_inside = false;
+                for (i, &(xi, yi)) in scaled.iter().enumerate() {
+                    let (xj, yj) = scaled[(i + scaled.len() - 1) % scaled.len()];
+                    if (yi > py) != (yj > py) && px < (xj - xi) * (py - yi) / (yj - yi) + xi {
+                        is_inside = !is_inside;
+                    }
+                }
+                inside.push(is_inside);
+            }
+        }
+
+        Self {
+            x: x0,
+            y: y0,
+            width: x1 - x0,
+            height: y1 - y0,
+            inside,
+        }
+    }
+
+    /// Replace the pixels inside the polygon. `pixel` takes the coordinates relative to the bounding box.
+    fn fill(&self, image: &mut RgbImage, pixel: impl Fn(u32, u32) -> Rgb<u8>) {
+        for y in 0..self.height {
+            for x in 0..self.width {
+                if self.inside[(y * self.width + x) as usize] {
+                    image.put_pixel(self.x + x, self.y + y, pixel(x, y));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn inside_count(region: &Region) -> usize {
+        region.inside.iter().filter(|&&inside| inside).count()
+    }
+
+    #[test]
+    fn rasterizes_a_rectangle_to_its_bounding_box() {
+        let region = Region::rasterize(
+            &[[0.25, 0.25], [0.75, 0.25], [0.75, 0.75], [0.25, 0.75]],
+            100,
+            100,
+        );
+
+        assert_eq!(
+            (region.x, region.y, region.width, region.height),
+            (25, 25, 50, 50)
+        );
+        assert_eq!(inside_count(&region), 50 * 50);
+    }
+
+    #[test]
+    fn rasterizes_a_triangle_at_the_centers_of_the_pixels() {
+        let region = Region::rasterize(&[[0.0, 0.0], [1.0, 0.0], [0.0, 1.0]], 10, 10);
+
+        // the pixel (x, y) is inside if x + 0.5 + y + 0.5 < 10
+        assert_eq!(inside_count(&region), (1..=9).sum::<usize>());
+        assert!(region.inside[0]);
+        assert!(!region.inside[9 * 10 + 9]);
+    }
+
+    #[test]
+    fn rasterizes_a_region_at_the_corner_of_the_frame() {
+        let region = Region::rasterize(&[[0.5, 0.5], [1.0, 0.5], [1.0, 1.0], [0.5, 1.0]], 8, 8);
+
+        assert_eq!(
+            (region.x, region.y, region.width, region.height),
+            (4, 4, 4, 4)
+        );
+        assert_eq!(inside_count(&region), 4 * 4);
+    }
+
+    #[test]
+    fn fills_only_the_pixels_inside_the_mask() {
+        let masks = PrivacyMasks::new(vec![MaskConfig {
+            points: vec![[0.0, 0.0], [0.5, 0.0], [0.5, 1.0], [0.0, 1.0]],
+            style: MaskStyle::Fill,
+            color: [255, 0, 0],
+            blur_sigma: default_blur_sigma(),
+        }]);
+        let mut image = RgbImage::new(10, 4);
+
+        masks.apply(&mut image);
+
+        for (x, _, pixel) in image.enumerate_pixels() {
+            let expected = if x < 5 { [255, 0, 0] } else { [0, 0, 0] };
+            assert_eq!(pixel.0, expected, "at x = {x}");
+        }
+    }
+}
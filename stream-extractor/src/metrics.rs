@@ -0,0 +1,337 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use prometheus::{
+    Encoder, GaugeVec, Histogram, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge,
+    IntGaugeVec, Opts, Registry, TextEncoder,
+};
+
+/// The state of the pipeline of a monitor.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PipelineState {
+    /// The pipeline is built and waiting for the first frames.
+    Starting,
+
+    /// The pipeline is playing.
+    Playing,
+
+    /// The pipeline failed or ended, and is waiting to be rebuilt.
+    Reconnecting,
+
+    /// The source is played to the end (files only).
+    Finished,
+}
+
+impl PipelineState {
+    const ALL: [Self; 4] = [
+        Self::Starting,
+        Self::Playing,
+        Self::Reconnecting,
+        Self::Finished,
+    ];
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Starting => "starting",
+            Self::Playing => "playing",
+            Self::Reconnecting => "reconnecting",
+            Self::Finished => "finished",
+        }
+    }
+}
+
+/// Why a frame is dropped before it reaches NATS.
+#[derive(Clone, Copy, Debug)]
+pub enum DropReason {
+    QueueFull,
+    EncodeFailed,
+    PublishFailed,
+    SpoolEvicted,
+}
+
+impl DropReason {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::QueueFull => "queue_full",
+            Self::EncodeFailed => "encode_failed",
+            Self::PublishFailed => "publish_failed",
+            Self::SpoolEvicted => "spool_evicted",
+        }
+    }
+}
+
+/// The Prometheus metrics of the extractor.
+pub struct Metrics {
+    registry: Registry,
+    frames_decoded: IntCounterVec,
+    frames_sampled: IntCounterVec,
+    frames_encoded: IntCounterVec,
+    frames_published: IntCounterVec,
+    frames_dropped: IntCounterVec,
+    reconnects: IntCounterVec,
+    pipeline_state: IntGaugeVec,
+    last_frame_age: GaugeVec,
+    publish_latency: HistogramVec,
+    pub spool_depth: IntGauge,
+    pub spool_bytes: IntGauge,
+    monitors: Mutex<Vec<MonitorMetrics>>,
+}
+
+impl Metrics {
+    pub fn new() -> anyhow::Result<Self> {
+        let registry = Registry::new_custom(Some("extractor".to_string()), None)
+            .context("Failed to create the metrics registry")?;
+
+        let frames_decoded = IntCounterVec::new(
+            Opts::new("frames_decoded_total", "The number of decoded frames."),
+            &["monitor"],
+        )?;
+        let frames_sampled = IntCounterVec::new(
+            Opts::new(
+                "frames_sampled_total",
+                "The number of frames dispatched to the queue by the sampler and the motion gate.",
+            ),
+            &["monitor"],
+        )?;
+        let frames_encoded = IntCounterVec::new(
+            Opts::new("frames_encoded_total", "The number of encoded frames."),
+            &["monitor"],
+        )?;
+        let frames_published = IntCounterVec::new(
+            Opts::new(
+                "frames_published_total",
+                "The number of frames published to NATS.",
+            ),
+            &["monitor"],
+        )?;
+        let frames_dropped = IntCounterVec::new(
+            Opts::new(
+                "frames_dropped_total",
+                "The number of sampled frames dropped before they reach NATS.",
+            ),
+            &["monitor", "reason"],
+        )?;
+        let reconnects = IntCounterVec::new(
+            Opts::new("reconnects_total", "The number of pipeline reconnections."),
+            &["monitor"],
+        )?;
+        let pipeline_state = IntGaugeVec::new(
+            Opts::new(
+                "pipeline_state",
+                "The state of the pipeline; 1 for the current state and 0 for the others.",
+            ),
+            &["monitor", "state"],
+        )?;
+        let last_frame_age = GaugeVec::new(
+            Opts::new(
+                "last_frame_age_seconds",
+                "The seconds since the last frame was decoded.",
+            ),
+            &["monitor"],
+        )?;
+        let publish_latency = HistogramVec::new(
+            HistogramOpts::new(
+                "publish_latency_seconds",
+                "The time to publish a frame to NATS, including the JetStream acknowledgement.",
+            ),
+            &["monitor"],
+        )?;
+
+        let spool_depth = IntGauge::new(
+            "spool_depth",
+            "The number of frames in the spool waiting for NATS.",
+        )?;
+        let spool_bytes = IntGauge::new("spool_bytes", "The size of the spool, in bytes.")?;
+
+        registry.register(Box::new(frames_decoded.clone()))?;
+        registry.register(Box::new(frames_sampled.clone()))?;
+        registry.register(Box::new(frames_encoded.clone()))?;
+        registry.register(Box::new(frames_published.clone()))?;
+        registry.register(Box::new(frames_dropped.clone()))?;
+        registry.register(Box::new(reconnects.clone()))?;
+        registry.register(Box::new(pipeline_state.clone()))?;
+        registry.register(Box::new(last_frame_age.clone()))?;
+        registry.register(Box::new(publish_latency.clone()))?;
+        registry.register(Box::new(spool_depth.clone()))?;
+        registry.register(Box::new(spool_bytes.clone()))?;
+
+        Ok(Self {
+            registry,
+            frames_decoded,
+            frames_sampled,
+            frames_encoded,
+            frames_published,
+            frames_dropped,
+            reconnects,
+            pipeline_state,
+            last_frame_age,
+            publish_latency,
+            spool_depth,
+            spool_bytes,
+            monitors: Mutex::new(Vec::new()),
+        })
+    }
+
+    /// Create the metrics of a monitor.
+    pub fn monitor(&self, monitor_id: &str) -> MonitorMetrics {
+        let labels = &[monitor_id];
+
+        let monitor_metrics = MonitorMetrics {
+            monitor_id: monitor_id.to_string(),
+            frames_decoded: self.frames_decoded.with_label_values(labels),
+            frames_sampled: self.frames_sampled.with_label_values(labels),
+            frames_encoded: self.frames_encoded.with_label_values(labels),
+            frames_published: self.frames_published.with_label_values(labels),
+            frames_dropped: self.frames_dropped.clone(),
+            reconnects: self.reconnects.with_label_values(labels),
+            pipeline_state: self.pipeline_state.clone(),
+            publish_latency: self.publish_latency.with_label_values(labels),
+            status: Arc::new(Mutex::new(MonitorStatus {
+                state: PipelineState::Starting,
+                state_since: Instant::now(),
+                last_frame_at: None,
+            })),
+        };
+        monitor_metrics.set_state(PipelineState::Starting);
+
+        self.monitors
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(monitor_metrics.clone());
+
+        monitor_metrics
+    }
+
+    /// Record a frame of the monitor published from the spool.
+    pub fn record_published(&self, monitor_id: &str) {
+        self.frames_published.with_label_values(&[monitor_id]).inc();
+    }
+
+    /// Record a frame of the monitor dropped from the spool.
+    pub fn record_spool_evicted(&self, monitor_id: &str) {
+        self.frames_dropped
+            .with_label_values(&[monitor_id, DropReason::SpoolEvicted.as_str()])
+            .inc();
+    }
+
+    /// The metrics of all the monitors.
+    pub fn monitors(&self) -> Vec<MonitorMetrics> {
+        self.monitors
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    /// Encode the metrics in the Prometheus text format.
+    pub fn encode(&self) -> anyhow::Result<String> {
+        // the age of the last frame is only known at the time of scraping
+        for monitor in self.monitors() {
+            if let Some(age) = monitor.status().last_frame_age() {
+                self.last_frame_age
+                    .with_label_values(&[&monitor.monitor_id])
+                    .set(age.as_secs_f64());
+            }
+        }
+
+        let mut buf = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buf)
+            .context("Failed to encode the metrics")?;
+
+        Ok(String::from_utf8(buf)?)
+    }
+}
+
+/// The metrics of a monitor.
+///
+/// The [`Clone`] operation is cheap.
+#[derive(Clone)]
+pub struct MonitorMetrics {
+    pub monitor_id: String,
+    pub frames_decoded: IntCounter,
+    pub frames_sampled: IntCounter,
+    pub frames_encoded: IntCounter,
+    pub frames_published: IntCounter,
+    frames_dropped: IntCounterVec,
+    pub reconnects: IntCounter,
+    pipeline_state: IntGaugeVec,
+    pub publish_latency: Histogram,
+    status: Arc<Mutex<MonitorStatus>>,
+}
+
+impl MonitorMetrics {
+    /// Record a decoded frame.
+    pub fn record_decoded(&self) {
+        self.frames_decoded.inc();
+        self.lock_status().last_frame_at = Some(Instant::now());
+    }
+
+    /// Record a dropped frame, returning the number of frames dropped for the reason so far.
+    pub fn record_dropped(&self, reason: DropReason) -> u64 {
+        let counter = self
+            .frames_dropped
+            .with_label_values(&[&self.monitor_id, reason.as_str()]);
+        counter.inc();
+        counter.get()
+    }
+
+    pub fn set_state(&self, state: PipelineState) {
+        for candidate in PipelineState::ALL {
+            self.pipeline_state
+                .with_label_values(&[&self.monitor_id, candidate.as_str()])
+                .set((candidate == state) as i64);
+        }
+
+        let mut status = self.lock_status();
+        if status.state != state {
+            status.state = state;
+            status.state_since = Instant::now();
+        }
+    }
+
+    /// A snapshot of the status of the pipeline.
+    pub fn status(&self) -> MonitorStatus {
+        *self.lock_status()
+    }
+
+    fn lock_status(&self) -> std::sync::MutexGuard<'_, MonitorStatus> {
+        self.status.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// The status of the pipeline of a monitor.
+#[derive(Clone, Copy, Debug)]
+pub struct MonitorStatus {
+    pub state: PipelineState,
+
+    /// When the pipeline entered the current state.
+    pub state_since: Instant,
+
+    /// When the last frame was decoded.
+    pub last_frame_at: Option<Instant>,
+}
+
+impl MonitorStatus {
+    pub fn last_frame_age(&self) -> Option<Duration> {
+        self.last_frame_at.map(|at| at.elapsed())
+    }
+
+    /// Check if the pipeline is playing but no frame has been decoded for `timeout`.
+    pub fn is_stalled(&self, timeout: Duration) -> bool {
+        if self.state != PipelineState::Playing {
+            return false;
+        }
+
+        // the pipeline may have just started playing
+        let since = match self.last_frame_at {
+            Some(at) => at.max(self.state_since),
+            None => self.state_since,
+        };
+
+        since.elapsed() > timeout
+    }
+}
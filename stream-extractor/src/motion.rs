@@ -0,0 +1,189 @@
+use std::time::{Duration, Instant};
+
+use image::{DynamicImage, imageops::FilterType};
+
+/// The width of the downscaled frame to detect motion on.
+const MOTION_FRAME_WIDTH: u32 = 160;
+
+/// The configuration of the motion gate.
+///
+/// When enabled, only the sampled frames with motion are dispatched
+/// to the inference queue.
+#[derive(Clone, Debug, serde::Deserialize)]
+#[serde(default)]
+pub struct MotionConfig {
+    /// How sensitive the detection is to the change of a pixel, from 0.0 to 1.0.
+    ///
+    /// A pixel is changed if its difference from the background is greater than
+    /// `(1.0 - sensitivity) * 255` in grayscale.
+    pub sensitivity: f32,
+
+    /// The minimum ratio of changed pixels to the whole frame, from 0.0 to 1.0,
+    /// to consider that there is motion in the frame.
+    pub min_area: f32,
+
+    /// How fast the background adapts to the new frames, from 0.0 to 1.0.
+    pub background_rate: f32,
+
+    /// Dispatch a frame every N minutes even if there is no motion.
+    pub keepalive_minutes: Option<u64>,
+}
+
+impl Default for MotionConfig {
+    fn default() -> Self {
+        Self {
+            sensitivity: 0.9,
+            min_area: 0.01,
+            background_rate: 0.05,
+            keepalive_minutes: None,
+        }
+    }
+}
+
+impl MotionConfig {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        for (name, value) in [
+            ("sensitivity", self.sensitivity),
+            ("min_area", self.min_area),
+            ("background_rate", self.background_rate),
+        ] {
+            if !(0.0..=1.0).contains(&value) {
+                anyhow::bail!("motion.{name} should be in the range of 0.0 to 1.0");
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Detect motion by comparing a downscaled grayscale of each frame
+/// against a rolling background.
+pub struct MotionDetector {
+    threshold: f32,
+    min_area: f32,
+    background_rate: f32,
+    keepalive: Option<Duration>,
+
+    background: Option<(u32, u32, Vec<f32>)>,
+    last_dispatched_at: Option<Instant>,
+}
+
+impl MotionDetector {
+    pub fn new(config: &MotionConfig) -> Self {
+        Self {
+            threshold: (1.0 - config.sensitivity) * 255.0,
+            min_area: config.min_area,
+            background_rate: config.background_rate,
+            keepalive: config
+                .keepalive_minutes
+                .map(|minutes| Duration::from_secs(minutes * 60)),
+            background: None,
+            last_dispatched_at: None,
+        }
+    }
+
+    /// Check if the frame should be dispatched, and update the background with it.
+    ///
+    /// The frame is dispatched if it has motion or the keep-alive interval has elapsed.
+    pub fn should_dispatch(&mut self, frame: &DynamicImage) -> bool {
+        let height = (frame.height() * MOTION_FRAME_WIDTH / frame.width().max(1)).max(1);
+        let gray = frame
+            .resize_exact(MOTION_FRAME_WIDTH, height, FilterType::Triangle)
+            .into_luma8();
+
+        let has_motion = match &mut self.background {
+            Some((width, bg_height, background))
+                if *width == gray.width() && *bg_height == gray.height() =>
+            {
+                let mut changed = 0usize;
+                for (background, pixel) in background.iter_mut().zip(gray.as_raw()) {
+                    let pixel = *pixel as f32;
+                    if (pixel - *background).abs() > self.threshold {
+                        changed += 1;
+                    }
+                    *background += (pixel - *background) * self.background_rate;
+                }
+
+                let changed_area = changed as f32 / background.len() as f32;
+                tracing::debug!("Changed area: {changed_area:.4}");
+
+                changed_area >= self.min_area
+            }
+            // The first frame (or the resolution changed): there is nothing to compare with.
+            _ => {
+                self.background = Some((
+                    gray.width(),
+                    gray.height(),
+                    gray.as_raw().iter().map(|pixel| *pixel as f32).collect(),
+                ));
+                true
+            }
+        };
+
+        let keepalive_due = match (self.keepalive, self.last_dispatched_at) {
+            (Some(keepalive), Some(last_dispatched_at)) => {
+                last_dispatched_at.elapsed() >= keepalive
+            }
+            _ => false,
+        };
+
+        if has_motion || keepalive_due {
+            self.last_dispatched_at = Some(Instant::now());
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgb, RgbImage};
+
+    use super::*;
+
+    /// A black frame with a white square at `x`.
+    fn frame_with_square_at(x: u32) -> DynamicImage {
+        let mut frame = RgbImage::new(320, 240);
+        for y in 100..140 {
+            for x in x..x + 40 {
+                frame.put_pixel(x, y, Rgb([255, 255, 255]));
+            }
+        }
+        DynamicImage::ImageRgb8(frame)
+    }
+
+    #[test]
+    fn dispatches_the_first_frame() {
+        let mut detector = MotionDetector::new(&MotionConfig::default());
+
+        assert!(detector.should_dispatch(&frame_with_square_at(20)));
+    }
+
+    #[test]
+    fn skips_a_still_frame() {
+        let mut detector = MotionDetector::new(&MotionConfig::default());
+        detector.should_dispatch(&frame_with_square_at(20));
+
+        assert!(!detector.should_dispatch(&frame_with_square_at(20)));
+    }
+
+    #[test]
+    fn dispatches_a_moved_frame() {
+        let mut detector = MotionDetector::new(&MotionConfig::default());
+        detector.should_dispatch(&frame_with_square_at(20));
+
+        assert!(detector.should_dispatch(&frame_with_square_at(200)));
+    }
+
+    #[test]
+    fn dispatches_a_still_frame_once_the_keepalive_is_due() {
+        let mut detector = MotionDetector::new(&MotionConfig {
+            keepalive_minutes: Some(0),
+            ..MotionConfig::default()
+        });
+        detector.should_dispatch(&frame_with_square_at(20));
+
+        assert!(detector.should_dispatch(&frame_with_square_at(20)));
+    }
+}
@@ -0,0 +1,146 @@
+use std::time::Duration;
+
+use crossbeam::channel::{Receiver, SendTimeoutError, Sender, TrySendError, bounded};
+
+use crate::{
+    metrics::{DropReason, MonitorMetrics},
+    source::{Pace, Source},
+    worker::ExtractedFrame,
+};
+
+/// The configuration of the queue between the pipeline and the NATS publisher.
+#[derive(Clone, Debug, serde::Deserialize)]
+#[serde(default)]
+pub struct QueueConfig {
+    /// The maximum number of frames waiting to be published.
+    pub capacity: usize,
+
+    /// What to do when the queue is full.
+    ///
+    /// Defaults to [`OverflowPolicy::DropNewest`], or to blocking without a timeout for
+    /// a file played with [`Pace::Fast`], which has no reason to lose frames.
+    pub overflow: Option<OverflowPolicy>,
+}
+
+impl Default for QueueConfig {
+    fn default() -> Self {
+        Self {
+            capacity: 30,
+            overflow: None,
+        }
+    }
+}
+
+impl QueueConfig {
+    /// The overflow policy of the queue of the source played at the pace.
+    pub fn overflow_policy(&self, source: &Source, pace: Pace) -> OverflowPolicy {
+        match self.overflow {
+            Some(overflow) => overflow,
+            None if !source.is_live() && pace == Pace::Fast => {
+                OverflowPolicy::Block { timeout_ms: None }
+            }
+            None => OverflowPolicy::default(),
+        }
+    }
+}
+
+/// What to do with a frame when the queue is full.
+#[derive(Clone, Copy, Debug, Default, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OverflowPolicy {
+    /// Drop the new frame.
+    #[default]
+    DropNewest,
+
+    /// Drop the oldest frame in the queue to make room for the new frame.
+    DropOldest,
+
+    /// Block the pipeline until there is room in the queue, and drop
+    /// the new frame if the queue is still full after the timeout, if any.
+    Block { timeout_ms: Option<u64> },
+}
+
+/// Create a queue of frames with the overflow policy.
+pub fn frame_queue(
+    config: &QueueConfig,
+    policy: OverflowPolicy,
+    metrics: MonitorMetrics,
+) -> (FrameSender, Receiver<ExtractedFrame>) {
+    let (sender, receiver) = bounded(config.capacity);
+
+    let frame_sender = FrameSender {
+        sender,
+        receiver: receiver.clone(),
+        policy,
+        metrics,
+    };
+
+    (frame_sender, receiver)
+}
+
+/// The sending half of the frame queue.
+///
+/// Sending never panics, and only blocks for [`OverflowPolicy::Block`]. The [`Clone`] operation is cheap.
+#[derive(Clone)]
+pub struct FrameSender {
+    sender: Sender<ExtractedFrame>,
+    /// Used to evict the oldest frame for [`OverflowPolicy::DropOldest`].
+    receiver: Receiver<ExtractedFrame>,
+    policy: OverflowPolicy,
+    metrics: MonitorMetrics,
+}
+
+impl FrameSender {
+    /// Send the frame to the queue, dropping a frame if the queue is full.
+    pub fn send(&self, frame: ExtractedFrame) {
+        let result = match self.policy {
+            OverflowPolicy::DropNewest => self.sender.try_send(frame),
+            OverflowPolicy::DropOldest => {
+                let mut frame = frame;
+                loop {
+                    match self.sender.try_send(frame) {
+                        Err(TrySendError::Full(returned_frame)) => {
+                            if let Ok(oldest_frame) = self.receiver.try_recv() {
+                                self.record_dropped(&oldest_frame);
+                            }
+                            frame = returned_frame;
+                        }
+                        result => break result,
+                    }
+                }
+            }
+            OverflowPolicy::Block {
+                timeout_ms: Some(timeout_ms),
+            } => self
+                .sender
+                .send_timeout(frame, Duration::from_millis(timeout_ms))
+                .map_err(|e| match e {
+                    SendTimeoutError::Timeout(frame) => TrySendError::Full(frame),
+                    SendTimeoutError::Disconnected(frame) => TrySendError::Disconnected(frame),
+                }),
+            OverflowPolicy::Block { timeout_ms: None } => self
+                .sender
+                .send(frame)
+                .map_err(|e| TrySendError::Disconnected(e.into_inner())),
+        };
+
+        match result {
+            Ok(()) => {}
+            Err(TrySendError::Full(frame)) => self.record_dropped(&frame),
+            Err(TrySendError::Disconnected(frame)) => {
+                tracing::error!(
+                    "The frame queue is closed; dropping frame {}. It should not happened :(",
+                    frame.id
+                );
+            }
+        }
+    }
+
+    fn record_dropped(&self, frame: &ExtractedFrame) {
+        let dropped = self.metrics.record_dropped(DropReason::QueueFull);
+        tracing::warn!(
+            "The frame queue is full; dropped frame {} ({dropped} frames dropped so far).",
+            frame.id
+        );
+    }
+}
@@ -0,0 +1,134 @@
+use std::time::Duration;
+
+use rand::Rng;
+
+/// The reconnection policy of the extractor workers.
+#[derive(Clone, Debug, serde::Deserialize)]
+#[serde(default)]
+pub struct ReconnectConfig {
+    /// The delay before the first reconnection, in milliseconds.
+    pub initial_backoff_ms: u64,
+
+    /// The upper bound of the reconnection delay, in milliseconds.
+    pub max_backoff_ms: u64,
+
+    /// How long a pipeline should keep playing before its backoff is reset, in milliseconds.
+    ///
+    /// A camera that keeps failing right after connecting backs off further and further,
+    /// while a camera that drops after streaming for a while reconnects quickly.
+    pub stable_after_ms: u64,
+}
+
+impl Default for ReconnectConfig {
+    fn default() -> Self {
+        Self {
+            initial_backoff_ms: 1_000,
+            max_backoff_ms: 60_000,
+            stable_after_ms: 60_000,
+        }
+    }
+}
+
+/// Exponential backoff with jitter.
+///
+/// The n-th delay is picked randomly from the upper half of
+/// `min(initial * 2^n, max)`, so that cameras dropped off at the same time
+/// do not reconnect at the same time.
+pub struct Backoff {
+    initial: Duration,
+    max: Duration,
+    attempt: u32,
+}
+
+impl Backoff {
+    pub fn new(config: &ReconnectConfig) -> Self {
+        Self {
+            initial: Duration::from_millis(config.initial_backoff_ms),
+            max: Duration::from_millis(config.max_backoff_ms),
+            attempt: 0,
+        }
+    }
+
+    /// Get the delay before the next attempt.
+    pub fn next_delay(&mut self) -> Duration {
+        let delay = self
+            .initial
+            .saturating_mul(2u32.saturating_pow(self.attempt))
+            .min(self.max);
+        self.attempt = self.attempt.saturating_add(1);
+
+        let half = delay / 2;
+        half + rand::thread_rng().gen_range(Duration::ZERO..=half)
+    }
+
+    /// Reset the backoff after a successful attempt.
+    pub fn reset(&mut self) {
+        self.attempt = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn backoff() -> Backoff {
+        Backoff::new(&ReconnectConfig {
+            initial_backoff_ms: 1_000,
+            max_backoff_ms: 8_000,
+            stable_after_ms: 60_000,
+        })
+    }
+
+    fn assert_between(delay: Duration, min_ms: u64, max_ms: u64) {
+        assert!(
+            (Duration::from_millis(min_ms)..=Duration::from_millis(max_ms)).contains(&delay),
+            "{delay:?} should be between {min_ms}ms and {max_ms}ms"
+        );
+    }
+
+    #[test]
+    fn doubles_the_delay_on_each_attempt() {
+        let mut backoff = backoff();
+
+        assert_between(backoff.next_delay(), 500, 1_000);
+        assert_between(backoff.next_delay(), 1_000, 2_000);
+        assert_between(backoff.next_delay(), 2_000, 4_000);
+        assert_between(backoff.next_delay(), 4_000, 8_000);
+    }
+
+    #[test]
+    fn caps_the_delay_at_the_max() {
+        let mut backoff = backoff();
+
+        for _ in 0..100 {
+            assert!(backoff.next_delay() <= Duration::from_millis(8_000));
+        }
+        assert_between(backoff.next_delay(), 4_000, 8_000);
+    }
+
+    #[test]
+    fn jitters_within_the_upper_half() {
+        let delays = (0..1_000)
+            .map(|_| backoff().next_delay())
+            .collect::<Vec<_>>();
+
+        for &delay in &delays {
+            assert_between(delay, 500, 1_000);
+        }
+        // the delays are spread, not all the same
+        assert!(delays.iter().any(|&delay| delay != delays[0]));
+    }
+
+    #[test]
+    fn restarts_from_the_initial_delay_after_a_reset() {
+        let mut backoff = backoff();
+        for _ in 0..5 {
+            backoff.next_delay();
+        }
+
+        backoff.reset();
+
+        assert_between(backoff.next_delay(), 500, 1_000);
+        assert_between(backoff.next_delay(), 1_000, 2_000);
+    }
+}
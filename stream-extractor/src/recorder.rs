@@ -0,0 +1,346 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime},
+};
+
+use anyhow::Context;
+use gst::prelude::*;
+use gstreamer::{self as gst};
+
+use crate::codec::is_video_pad;
+
+/// The configuration of the rolling recording of a monitor.
+///
+/// The encoded stream is recorded into short MP4 segments on the local disk,
+/// so that a clip around a detection can be exported later.
+#[derive(Clone, Debug, serde::Deserialize)]
+#[serde(default)]
+pub struct RecordingConfig {
+    /// The directory of the segments. Each monitor records into its own subdirectory.
+    pub directory: PathBuf,
+
+    /// The length of a segment, in seconds. A clip is cut at the boundaries of the segments.
+    pub segment_secs: u64,
+
+    /// How many seconds before the detection are included in a clip.
+    pub pre_secs: u64,
+
+    /// How many seconds after the detection are included in a clip.
+    pub post_secs: u64,
+}
+
+impl Default for RecordingConfig {
+    fn default() -> Self {
+        Self {
+            directory: PathBuf::from("recordings"),
+            segment_secs: 5,
+            pre_secs: 10,
+            post_secs: 10,
+        }
+    }
+}
+
+impl RecordingConfig {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.segment_secs == 0 {
+            anyhow::bail!("segment_secs should be greater than 0");
+        }
+        if self.pre_secs == 0 && self.post_secs == 0 {
+            anyhow::bail!("either pre_secs or post_secs should be greater than 0");
+        }
+
+        Ok(())
+    }
+}
+
+/// How much longer than the length of a clip its export may take, for the pipeline to start.
+const EXPORT_TIMEOUT_MARGIN: Duration = Duration::from_secs(30);
+
+/// A closed segment on the disk.
+#[derive(Clone, Debug)]
+struct Segment {
+    path: PathBuf,
+    start: SystemTime,
+    end: SystemTime,
+}
+
+/// The rolling recording of a monitor.
+///
+/// It keeps the segments long enough to cover the clips, and deletes the older ones.
+/// The [`Clone`] operation is cheap.
+#[derive(Clone)]
+pub struct Recorder {
+    config: RecordingConfig,
+    directory: PathBuf,
+    state: Arc<Mutex<RecorderState>>,
+}
+
+#[derive(Default)]
+struct RecorderState {
+    /// The segments being recorded, with their start time.
+    opened: HashMap<String, SystemTime>,
+    /// The closed segments, ordered by their start time.
+    closed: VecDeque<Segment>,
+}
+
+impl Recorder {
+    /// Create a recorder of a monitor, removing the segments left by the previous runs.
+    pub fn new(monitor_id: &str, config: RecordingConfig) -> anyhow::Result<Self> {
+        let directory = config.directory.join(monitor_id);
+
+        if directory.exists() {
+            std::fs::remove_dir_all(&directory).with_context(|| {
+                format!("Failed to clean the recording directory {directory:?}")
+            })?;
+        }
+        std::fs::create_dir_all(directory.join("clips"))
+            .with_context(|| format!("Failed to create the recording directory {directory:?}"))?;
+
+        Ok(Self {
+            config,
+            directory,
+            state: Arc::default(),
+        })
+    }
+
+    pub fn config(&self) -> &RecordingConfig {
+        &self.config
+    }
+
+    /// Add a `queue ! splitmuxsink` branch recording the encoded stream to the pipeline.
+    ///
+    /// Returns the first element of the branch, which accepts the parsed stream.
+    pub fn build(&self, pipeline: &gst::Pipeline) -> anyhow::Result<gst::Element> {
+        // Name the segments after the run, so the segments of the previous runs are not overwritten.
+        let run_id = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        let location = self.directory.join(format!("{run_id}-%05d.mp4"));
+
+        // Never block the decoding branch if the disk is slow.
+        let queue_element = gst::ElementFactory::make("queue")
+            .property_from_str("leaky", "downstream")
+            .build()
+            .context("failed to create queue element")?;
+
+        let splitmuxsink_element = gst::ElementFactory::make("splitmuxsink")
+            .property("location", location.to_string_lossy().as_ref())
+            .property(
+                "max-size-time",
+                gst::ClockTime::from_seconds(self.config.segment_secs).nseconds(),
+            )
+            .property("async-finalize", true)
+            .build()
+            .context("failed to create splitmuxsink element")?;
+
+        pipeline.add_many([&queue_element, &splitmuxsink_element])?;
+        queue_element.link(&splitmuxsink_element)?;
+
+        Ok(queue_element)
+    }
+
+    /// Track the segments from the messages of `splitmuxsink`.
+    ///
+    /// The running time in the messages is converted to the wall clock with the `base_time`
+    /// of the pipeline, which uses the wall clock.
+    pub fn handle_message(&self, message: &gst::Message, base_time: Option<gst::ClockTime>) {
+        let gst::MessageView::Element(element) = message.view() else {
+            return;
+        };
+        let Some(structure) = element.structure() else {
+            return;
+        };
+
+        let opened = match structure.name().as_str() {
+            "splitmuxsink-fragment-opened" => true,
+            "splitmuxsink-fragment-closed" => false,
+            _ => return,
+        };
+
+        let (Ok(location), Ok(running_time)) = (
+            structure.get::<String>("location"),
+            structure.get::<gst::ClockTime>("running-time"),
+        ) else {
+            tracing::warn!("Received an invalid message from splitmuxsink: {structure}");
+            return;
+        };
+        let time = base_time
+            .map(|base_time| base_time + running_time)
+            .and_then(|time| {
+                SystemTime::UNIX_EPOCH.checked_add(Duration::from_nanos(time.nseconds()))
+            })
+            .unwrap_or_else(SystemTime::now);
+
+        let mut state = self.lock_state();
+        if opened {
+            state.opened.insert(location, time);
+            return;
+        }
+
+        let Some(start) = state.opened.remove(&location) else {
+            return;
+        };
+        tracing::debug!("Recorded segment {location}");
+        state.closed.push_back(Segment {
+            path: PathBuf::from(location),
+            start,
+            end: time,
+        });
+
+        // Keep the segments long enough for the clips waiting for their post-event part.
+        let retention = Duration::from_secs(
+            self.config.pre_secs + self.config.post_secs + 2 * self.config.segment_secs,
+        );
+        let expire_before = SystemTime::now() - retention;
+        while let Some(segment) = state.closed.front()
+            && segment.end < expire_before
+        {
+            if let Err(e) = std::fs::remove_file(&segment.path) {
+                tracing::warn!("Failed to remove segment {:?}: {:?}", segment.path, e);
+            }
+            state.closed.pop_front();
+        }
+    }
+
+    /// Export the segments covering `start..end` to a single MP4 file.
+    ///
+    /// Returns the path of the clip, or `None` if no segment covers the range.
+    /// It blocks until the clip is written.
+    pub fn export(&self, start: SystemTime, end: SystemTime) -> anyhow::Result<Option<PathBuf>> {
+        let segments = self
+            .lock_state()
+            .closed
+            .iter()
+            .filter(|segment| segment.start < end && segment.end > start)
+            .cloned()
+            .collect::<Vec<_>>();
+        if segments.is_empty() {
+            return Ok(None);
+        }
+
+        // Link the segments to a directory of their own, so they are not removed
+        // while exporting, and splitmuxsrc reads them in order.
+        let clip_id = uuid::Uuid::now_v7();
+        let segment_directory = self.directory.join("clips").join(clip_id.to_string());
+        std::fs::create_dir_all(&segment_directory)
+            .context("Failed to create the directory of the clip")?;
+        for (index, segment) in segments.iter().enumerate() {
+            std::fs::hard_link(
+                &segment.path,
+                segment_directory.join(format!("{index:05}.mp4")),
+            )
+            .with_context(|| format!("Failed to link segment {:?}", segment.path))?;
+        }
+
+        // Remuxing is much faster than real time, so a clip taking longer than its own length
+        // means the pipeline is stuck.
+        let clip_length = segments
+            .iter()
+            .map(|segment| {
+                segment
+                    .end
+                    .duration_since(segment.start)
+                    .unwrap_or_default()
+            })
+            .sum::<Duration>();
+        let timeout = clip_length + EXPORT_TIMEOUT_MARGIN;
+
+        let clip_path = self.directory.join("clips").join(format!("{clip_id}.mp4"));
+        let result = concat_segments(&segment_directory, &clip_path, timeout);
+
+        if let Err(e) = std::fs::remove_dir_all(&segment_directory) {
+            tracing::warn!("Failed to remove {segment_directory:?}: {:?}", e);
+        }
+
+        result.map(|()| Some(clip_path))
+    }
+
+    fn lock_state(&self) -> std::sync::MutexGuard<'_, RecorderState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Remux the segments in the directory into a single MP4 file, failing if it takes longer than
+/// the timeout.
+fn concat_segments(
+    segment_directory: &Path,
+    clip_path: &Path,
+    timeout: Duration,
+) -> anyhow::Result<()> {
+    let pipeline = gst::Pipeline::new();
+
+    let splitmuxsrc_element = gst::ElementFactory::make("splitmuxsrc")
+        .property(
+            "location",
+            segment_directory.join("*.mp4").to_string_lossy().as_ref(),
+        )
+        .build()
+        .context("failed to create splitmuxsrc element")?;
+
+    let mp4mux_element = gst::ElementFactory::make("mp4mux")
+        .build()
+        .context("failed to create mp4mux element")?;
+
+    let filesink_element = gst::ElementFactory::make("filesink")
+        .property("location", clip_path.to_string_lossy().as_ref())
+        .build()
+        .context("failed to create filesink element")?;
+
+    pipeline.add_many([&splitmuxsrc_element, &mp4mux_element, &filesink_element])?;
+    mp4mux_element.link(&filesink_element)?;
+
+    let mp4mux_element_clone = mp4mux_element.clone();
+    splitmuxsrc_element.connect_pad_added(move |_, src_pad| {
+        if !is_video_pad(src_pad) {
+            return;
+        }
+
+        let Some(sink_pad) = mp4mux_element_clone.request_pad_simple("video_%u") else {
+            tracing::warn!("Failed to request a video pad from mp4mux");
+            return;
+        };
+        if let Err(err) = src_pad.link(&sink_pad) {
+            tracing::warn!("Failed to link pads: {:?}", err);
+        }
+    });
+
+    pipeline
+        .set_state(gst::State::Playing)
+        .context("failed to start the clip pipeline")?;
+
+    let mut result = Ok(());
+    let bus = pipeline.bus().context("failed to get bus")?;
+    let deadline = Instant::now() + timeout;
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        let Some(msg) = bus.timed_pop(gst::ClockTime::from_nseconds(remaining.as_nanos() as u64))
+        else {
+            result = Err(anyhow::anyhow!(
+                "Exporting the clip timed out after {timeout:?}"
+            ));
+            break;
+        };
+
+        match msg.view() {
+            gst::MessageView::Eos(..) => break,
+            gst::MessageView::Error(err) => {
+                result = Err(anyhow::anyhow!(
+                    "Error from {}: {}",
+                    err.src().map(|s| s.path_string()).unwrap_or("<?>".into()),
+                    err.error()
+                ));
+                break;
+            }
+            _ => (),
+        }
+    }
+
+    pipeline
+        .set_state(gst::State::Null)
+        .context("failed to stop the clip pipeline")?;
+
+    result
+}
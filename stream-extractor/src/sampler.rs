@@ -0,0 +1,209 @@
+use std::time::Duration;
+
+use gstreamer as gst;
+
+/// How frames are sampled from the stream and dispatched to the inference queue.
+#[derive(Clone, Copy, Debug, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Sampling {
+    /// Dispatch one frame every N decoded frames.
+    ///
+    /// The actual sampling rate depends on the FPS of the camera.
+    FrameInterval(usize),
+
+    /// Dispatch one frame every N milliseconds of the stream.
+    IntervalMs(u64),
+
+    /// Dispatch K frames per second of the stream.
+    Fps(f64),
+}
+
+impl Default for Sampling {
+    /// Dispatch a frame every 300 frames (10s at 30fps, 5s at 60fps).
+    fn default() -> Self {
+        Self::FrameInterval(300)
+    }
+}
+
+impl Sampling {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        match *self {
+            Self::FrameInterval(0) => anyhow::bail!("frame_interval should be greater than 0"),
+            Self::IntervalMs(0) => anyhow::bail!("interval_ms should be greater than 0"),
+            Self::Fps(fps) if !(fps.is_finite() && fps > 0.0) => {
+                anyhow::bail!("fps should be a positive number")
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Decide which frames should be dispatched according to [`Sampling`].
+pub struct Sampler {
+    mode: SamplerMode,
+}
+
+enum SamplerMode {
+    Counter {
+        interval: usize,
+        counter: usize,
+    },
+    Timer {
+        interval: gst::ClockTime,
+        next_due: Option<gst::ClockTime>,
+    },
+}
+
+impl Sampler {
+    pub fn new(sampling: Sampling) -> Self {
+        let mode = match sampling {
+            Sampling::FrameInterval(interval) => SamplerMode::Counter {
+                interval,
+                counter: 0,
+            },
+            Sampling::IntervalMs(ms) => SamplerMode::Timer {
+                interval: gst::ClockTime::from_mseconds(ms),
+                next_due: None,
+            },
+            Sampling::Fps(fps) => SamplerMode::Timer {
+                interval: gst::ClockTime::from_nseconds(
+                    Duration::from_secs_f64(1.0 / fps).as_nanos() as u64,
+                ),
+                next_due: None,
+            },
+        };
+
+        Self { mode }
+    }
+
+    /// Check if the current frame should be dispatched.
+    ///
+    /// `timestamp` is the running time of the frame. It is required for
+    /// the time-based sampling; frames without timestamps are never dispatched.
+    pub fn should_sample(&mut self, timestamp: Option<gst::ClockTime>) -> bool {
+        match &mut self.mode {
+            SamplerMode::Counter { interval, counter } => {
+                let sample = counter.is_multiple_of(*interval);
+                *counter = counter.wrapping_add(1);
+                sample
+            }
+            SamplerMode::Timer { interval, next_due } => {
+                let Some(timestamp) = timestamp else {
+                    return false;
+                };
+
+                match *next_due {
+                    // the timestamps jumped backwards (e.g. a new segment), start over
+                    Some(due) if timestamp + *interval < due => {
+                        *next_due = Some(timestamp + *interval);
+                        true
+                    }
+                    Some(due) if timestamp < due => false,
+                    // keep the cadence, unless we are lagging more than one interval behind
+                    Some(due) if timestamp < due + *interval => {
+                        *next_due = Some(due + *interval);
+                        true
+                    }
+                    _ => {
+                        *next_due = Some(timestamp + *interval);
+                        true
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(ms: u64) -> Option<gst::ClockTime> {
+        Some(gst::ClockTime::from_mseconds(ms))
+    }
+
+    fn sample_all(sampler: &mut Sampler, timestamps: &[Option<gst::ClockTime>]) -> Vec<bool> {
+        timestamps
+            .iter()
+            .map(|&timestamp| sampler.should_sample(timestamp))
+            .collect()
+    }
+
+    #[test]
+    fn frame_interval_dispatches_the_first_frame_then_every_nth() {
+        let mut sampler = Sampler::new(Sampling::FrameInterval(3));
+
+        let sampled = sample_all(&mut sampler, &[None; 7]);
+
+        assert_eq!(sampled, [true, false, false, true, false, false, true]);
+    }
+
+    #[test]
+    fn frame_interval_ignores_the_timestamps() {
+        let mut sampler = Sampler::new(Sampling::FrameInterval(2));
+
+        let sampled = sample_all(&mut sampler, &[ms(500), ms(0), None, ms(100)]);
+
+        assert_eq!(sampled, [true, false, true, false]);
+    }
+
+    #[test]
+    fn interval_ms_dispatches_the_first_frame_then_on_the_boundaries() {
+        let mut sampler = Sampler::new(Sampling::IntervalMs(100));
+
+        let sampled = sample_all(
+            &mut sampler,
+            &[ms(0), ms(50), ms(99), ms(100), ms(199), ms(200)],
+        );
+
+        assert_eq!(sampled, [true, false, false, true, false, true]);
+    }
+
+    #[test]
+    fn interval_ms_keeps_the_cadence_of_late_frames() {
+        let mut sampler = Sampler::new(Sampling::IntervalMs(100));
+
+        let sampled = sample_all(&mut sampler, &[ms(0), ms(120), ms(190), ms(200)]);
+
+        assert_eq!(sampled, [true, true, false, true]);
+    }
+
+    #[test]
+    fn interval_ms_starts_over_after_a_gap() {
+        let mut sampler = Sampler::new(Sampling::IntervalMs(100));
+
+        let sampled = sample_all(&mut sampler, &[ms(0), ms(350), ms(400), ms(450)]);
+
+        assert_eq!(sampled, [true, true, false, true]);
+    }
+
+    #[test]
+    fn interval_ms_starts_over_when_the_timestamps_go_backwards() {
+        let mut sampler = Sampler::new(Sampling::IntervalMs(100));
+
+        let sampled = sample_all(
+            &mut sampler,
+            &[ms(1_000), ms(1_100), ms(10), ms(50), ms(110)],
+        );
+
+        assert_eq!(sampled, [true, true, true, false, true]);
+    }
+
+    #[test]
+    fn interval_ms_skips_the_frames_without_timestamps() {
+        let mut sampler = Sampler::new(Sampling::IntervalMs(100));
+
+        let sampled = sample_all(&mut sampler, &[None, ms(0), None, ms(100)]);
+
+        assert_eq!(sampled, [false, true, false, true]);
+    }
+
+    #[test]
+    fn fps_dispatches_frames_at_the_rate() {
+        let mut sampler = Sampler::new(Sampling::Fps(10.0));
+
+        let sampled = sample_all(&mut sampler, &[ms(0), ms(50), ms(100), ms(150), ms(200)]);
+
+        assert_eq!(sampled, [true, false, true, false, true]);
+    }
+}
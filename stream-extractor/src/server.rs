@@ -0,0 +1,159 @@
+use std::{net::SocketAddr, str::FromStr, sync::Arc, time::Duration};
+
+use anyhow::Context;
+use async_nats::connection::State;
+use poem::{
+    EndpointExt, IntoResponse, Route, Server, get, handler,
+    http::StatusCode,
+    listener::TcpListener,
+    web::{Data, Json},
+};
+
+use tokio_util::sync::CancellationToken;
+
+use crate::metrics::{Metrics, PipelineState};
+
+/// The configuration of the HTTP server of the health checks and the metrics.
+#[derive(Clone, Debug, serde::Deserialize)]
+#[serde(default)]
+pub struct ServerConfig {
+    /// The address to listen on.
+    pub bind_addr: String,
+
+    /// A playing pipeline is considered stalled if no frame is decoded for this long.
+    pub stall_timeout_secs: u64,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            bind_addr: "0.0.0.0:9090".to_string(),
+            stall_timeout_secs: 60,
+        }
+    }
+}
+
+#[derive(Clone)]
+struct ServerState {
+    metrics: Arc<Metrics>,
+    nats_client: async_nats::Client,
+    stall_timeout: Duration,
+}
+
+#[derive(serde::Serialize)]
+struct MonitorHealth {
+    monitor_id: String,
+    state: PipelineState,
+    last_frame_age_secs: Option<f64>,
+    stalled: bool,
+}
+
+#[derive(serde::Serialize)]
+struct Readiness {
+    nats_connected: bool,
+    monitors: Vec<MonitorHealth>,
+}
+
+impl ServerState {
+    fn monitor_healths(&self) -> Vec<MonitorHealth> {
+        self.metrics
+            .monitors()
+            .into_iter()
+            .map(|monitor| {
+                let status = monitor.status();
+                MonitorHealth {
+                    monitor_id: monitor.monitor_id,
+                    state: status.state,
+                    last_frame_age_secs: status.last_frame_age().map(|age| age.as_secs_f64()),
+                    stalled: status.is_stalled(self.stall_timeout),
+                }
+            })
+            .collect()
+    }
+}
+
+/// Healthy unless a pipeline is playing but stalled.
+///
+/// A failed pipeline is not unhealthy, since it is reconnected by the extractor itself.
+#[handler]
+async fn healthz(Data(state): Data<&ServerState>) -> impl IntoResponse {
+    let monitors = state.monitor_healths();
+    let status = if monitors.iter().any(|monitor| monitor.stalled) {
+        StatusCode::SERVICE_UNAVAILABLE
+    } else {
+        StatusCode::OK
+    };
+
+    Json(monitors).with_status(status)
+}
+
+/// Ready if NATS is connected and every pipeline is playing (or has finished its file).
+#[handler]
+async fn readyz(Data(state): Data<&ServerState>) -> impl IntoResponse {
+    let monitors = state.monitor_healths();
+    let nats_connected = state.nats_client.connection_state() == State::Connected;
+    let pipelines_ready = monitors.iter().all(|monitor| {
+        matches!(
+            monitor.state,
+            PipelineState::Playing | PipelineState::Finished
+        )
+    });
+
+    let status = if nats_connected && pipelines_ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+
+    Json(Readiness {
+        nats_connected,
+        monitors,
+    })
+    .with_status(status)
+}
+
+#[handler]
+async fn prometheus_metrics(Data(state): Data<&ServerState>) -> impl IntoResponse {
+    match state.metrics.encode() {
+        Ok(body) => body
+            .with_content_type("text/plain; version=0.0.4")
+            .into_response(),
+        Err(e) => {
+            tracing::error!("Failed to encode the metrics: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Serve `/healthz`, `/readyz` and `/metrics` until the shutdown.
+pub async fn serve(
+    config: ServerConfig,
+    metrics: Arc<Metrics>,
+    nats_client: async_nats::Client,
+    shutdown: CancellationToken,
+) -> anyhow::Result<()> {
+    let addr = SocketAddr::from_str(&config.bind_addr).context("Invalid server.bind_addr")?;
+
+    let state = ServerState {
+        metrics,
+        nats_client,
+        stall_timeout: Duration::from_secs(config.stall_timeout_secs),
+    };
+
+    let app = Route::new()
+        .at("/healthz", get(healthz))
+        .at("/readyz", get(readyz))
+        .at("/metrics", get(prometheus_metrics))
+        .data(state);
+
+    tracing::info!("Serving the health checks and the metrics on {addr}");
+
+    Server::new(TcpListener::bind(addr))
+        .run_with_graceful_shutdown(
+            app,
+            shutdown.cancelled_owned(),
+            Some(Duration::from_secs(1)),
+        )
+        .await
+        .context("Failed to run the HTTP server")
+}
@@ -0,0 +1,131 @@
+use anyhow::Context;
+use async_nats::HeaderMap;
+use bytes::Bytes;
+use futures::StreamExt as _;
+use tokio_util::sync::CancellationToken;
+
+use crate::{encoding::Encoding, queue::FrameSender, worker::LatestFrame};
+
+/// The request of a snapshot. The payload may be empty.
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(default)]
+struct SnapshotRequest {
+    /// Also push the snapshot to `frames` for recognition.
+    recognize: bool,
+}
+
+/// Answer the snapshot requests of a monitor on `snapshot.<monitor_id>`.
+///
+/// The reply is the latest decoded frame in the encoding of the monitor, with
+/// the same headers as the frames. On failure, the reply is empty with the
+/// `Nats-Service-Error` and `Nats-Service-Error-Code` headers.
+pub async fn serve_snapshots(
+    client: async_nats::Client,
+    monitor_id: String,
+    encoding: Encoding,
+    latest_frame: LatestFrame,
+    sender: FrameSender,
+    shutdown: CancellationToken,
+) -> anyhow::Result<()> {
+    let subject = format!("snapshot.{monitor_id}");
+    let mut subscriber = client
+        .subscribe(subject.clone())
+        .await
+        .with_context(|| format!("Failed to subscribe to {subject}"))?;
+
+    loop {
+        let message = tokio::select! {
+            message = subscriber.next() => message,
+            _ = shutdown.cancelled() => None,
+        };
+        let Some(message) = message else {
+            break;
+        };
+
+        let Some(reply) = message.reply else {
+            tracing::warn!("Received a snapshot request without a reply subject; skipping.");
+            continue;
+        };
+
+        let request = if message.payload.is_empty() {
+            SnapshotRequest::default()
+        } else {
+            match serde_json::from_slice::<SnapshotRequest>(&message.payload) {
+                Ok(request) => request,
+                Err(e) => {
+                    let headers = error_headers(400, &format!("Invalid request: {e}"));
+                    publish_reply(&client, reply, headers, Bytes::new()).await;
+                    continue;
+                }
+            }
+        };
+
+        tracing::info!("Taking a snapshot of monitor {monitor_id}: {request:?}");
+
+        let (headers, payload) =
+            match take_snapshot(&monitor_id, encoding, &latest_frame, &sender, &request).await {
+                Ok(Some((headers, payload))) => (headers, payload),
+                Ok(None) => (
+                    error_headers(503, "No frame has been decoded yet"),
+                    Bytes::new(),
+                ),
+                Err(e) => {
+                    tracing::error!("Failed to take a snapshot: {:?}", e);
+                    (error_headers(500, &format!("{e:#}")), Bytes::new())
+                }
+            };
+
+        publish_reply(&client, reply, headers, payload).await;
+    }
+
+    Ok(())
+}
+
+async fn take_snapshot(
+    monitor_id: &str,
+    encoding: Encoding,
+    latest_frame: &LatestFrame,
+    sender: &FrameSender,
+    request: &SnapshotRequest,
+) -> anyhow::Result<Option<(HeaderMap, Bytes)>> {
+    let latest_frame = latest_frame.clone();
+    let Some(frame) = tokio::task::spawn_blocking(move || latest_frame.extract())
+        .await
+        .context("Failed to create a thread to extract the frame")??
+    else {
+        return Ok(None);
+    };
+
+    let image = frame.image.clone();
+    let payload = tokio::task::spawn_blocking(move || encoding.encode(&image))
+        .await
+        .context("Failed to create a thread to encode the frame")??;
+
+    let headers = crate::frame_headers(monitor_id, encoding, &frame);
+
+    if request.recognize {
+        // sending may block for the overflow policy of the queue
+        let sender = sender.clone();
+        tokio::task::spawn_blocking(move || sender.send(frame));
+    }
+
+    Ok(Some((headers, payload)))
+}
+
+fn error_headers(code: u16, description: &str) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.append("Nats-Service-Error-Code", code.to_string());
+    headers.append("Nats-Service-Error", description);
+    headers
+}
+
+async fn publish_reply(
+    client: &async_nats::Client,
+    reply: async_nats::Subject,
+    headers: HeaderMap,
+    payload: Bytes,
+) {
+    if let Err(e) = client.publish_with_headers(reply, headers, payload).await {
+        tracing::warn!("Failed to reply to the snapshot request: {:?}", e);
+    }
+}
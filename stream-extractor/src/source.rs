@@ -0,0 +1,276 @@
+use std::fmt::Display;
+
+use anyhow::Context;
+use gst::prelude::*;
+use gstreamer::{self as gst};
+
+use crate::codec::{Codec, link_video_pad};
+
+/// The input source of the extractor, chosen from the URI scheme.
+#[derive(Clone, Debug, serde::Deserialize)]
+#[serde(try_from = "String")]
+pub enum Source {
+    /// `rtsp://` or `rtsps://` streams.
+    Rtsp(String),
+    /// `file://` video files.
+    File(String),
+    /// `http://` or `https://` streams, e.g. MJPEG or HLS.
+    Http(String),
+    /// `/dev/video*` devices, optionally prefixed with `v4l2://`.
+    V4l2(String),
+    /// `test://` or `test://<pattern>` test patterns of `videotestsrc`.
+    Test(Option<String>),
+}
+
+impl TryFrom<String> for Source {
+    type Error = anyhow::Error;
+
+    fn try_from(url: String) -> Result<Self, Self::Error> {
+        if url.starts_with("/dev/video") {
+            return Ok(Self::V4l2(url));
+        }
+
+        let (scheme, rest) = url
+            .split_once("://")
+            .with_context(|| format!("missing scheme in source URL: {url}"))?;
+
+        match scheme {
+            "rtsp" | "rtsps" => Ok(Self::Rtsp(url)),
+            "http" | "https" => Ok(Self::Http(url)),
+            "file" => Ok(Self::File(rest.to_string())),
+            "v4l2" => Ok(Self::V4l2(rest.to_string())),
+            "test" => Ok(Self::Test(Some(rest.to_string()).filter(|p| !p.is_empty()))),
+            _ => anyhow::bail!("unsupported scheme of source URL: {url}"),
+        }
+    }
+}
+
+impl Display for Source {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Rtsp(url) | Self::Http(url) => write!(f, "{url}"),
+            Self::File(path) => write!(f, "file://{path}"),
+            Self::V4l2(device) => write!(f, "v4l2://{device}"),
+            Self::Test(pattern) => write!(f, "test://{}", pattern.as_deref().unwrap_or_default()),
+        }
+    }
+}
+
+impl Source {
+    /// Whether the source is expected to stream endlessly.
+    ///
+    /// A live source is reconnected when it ends, while a non-live source
+    /// (i.e. a file) finishes at the end of the stream.
+    pub fn is_live(&self) -> bool {
+        !matches!(self, Self::File(_))
+    }
+
+    /// Whether the [`Codec`] can be overridden for this source.
+    ///
+    /// Only the RTP streams need to be depayloaded with a specific codec;
+    /// the other sources are always negotiated from the caps.
+    pub fn supports_codec_override(&self) -> bool {
+        matches!(self, Self::Rtsp(_))
+    }
+
+    /// Whether the encoded stream of the source can be recorded.
+    ///
+    /// Only the encoded live streams are recorded; the raw video of the devices
+    /// and the test patterns would have to be encoded again.
+    pub fn supports_recording(&self) -> bool {
+        matches!(self, Self::Rtsp(_) | Self::Http(_))
+    }
+
+    /// Add the source and decoding elements to the pipeline.
+    ///
+    /// The decoded stream is linked to `downstream`, and the encoded stream to `recorder`
+    /// if set and [supported](Self::supports_recording).
+    pub fn build(
+        &self,
+        pipeline: &gst::Pipeline,
+        codec: Codec,
+        downstream: &gst::Element,
+        recorder: Option<&gst::Element>,
+    ) -> anyhow::Result<()> {
+        match self {
+            Self::Rtsp(url) => {
+                let rtspsrc_element = gst::ElementFactory::make("rtspsrc")
+                    .property("location", url)
+                    .build()
+                    .context("failed to create rtspsrc element")?;
+
+                // Attach the NTP time from the RTCP sender reports to the buffers (GStreamer 1.22+),
+                // so that we know when the frames were captured by the camera.
+                if rtspsrc_element
+                    .find_property("add-reference-timestamp-meta")
+                    .is_some()
+                {
+                    rtspsrc_element.set_property("add-reference-timestamp-meta", true);
+                }
+
+                pipeline.add(&rtspsrc_element)?;
+
+                let decoder_element = codec
+                    .build_decoder(pipeline, downstream, recorder)
+                    .context("failed to create decoder elements")?;
+
+                rtspsrc_element.connect_pad_added(move |_, src_pad| {
+                    link_video_pad(src_pad, &decoder_element);
+                });
+            }
+            Self::File(path) => {
+                let filesrc_element = gst::ElementFactory::make("filesrc")
+                    .property("location", path)
+                    .build()
+                    .context("failed to create filesrc element")?;
+
+                pipeline.add(&filesrc_element)?;
+
+                let decoder_element = Codec::Auto
+                    .build_decoder(pipeline, downstream, None)
+                    .context("failed to create decoder elements")?;
+
+                filesrc_element.link(&decoder_element)?;
+            }
+            Self::Http(url) => {
+                let souphttpsrc_element = gst::ElementFactory::make("souphttpsrc")
+                    .property("location", url)
+                    .property("is-live", true)
+                    .build()
+                    .context("failed to create souphttpsrc element")?;
+
+                pipeline.add(&souphttpsrc_element)?;
+
+                let decoder_element = Codec::Auto
+                    .build_decoder(pipeline, downstream, recorder)
+                    .context("failed to create decoder elements")?;
+
+                souphttpsrc_element.link(&decoder_element)?;
+            }
+            Self::V4l2(device) => {
+                let v4l2src_element = gst::ElementFactory::make("v4l2src")
+                    .property("device", device)
+                    .build()
+                    .context("failed to create v4l2src element")?;
+
+                // The device may produce either raw or encoded (e.g. MJPEG) frames.
+                let decodebin_element = gst::ElementFactory::make("decodebin")
+                    .build()
+                    .context("failed to create decodebin element")?;
+
+                pipeline.add_many([&v4l2src_element, &decodebin_element])?;
+                v4l2src_element.link(&decodebin_element)?;
+
+                let downstream = downstream.clone();
+                decodebin_element.connect_pad_added(move |_, src_pad| {
+                    link_video_pad(src_pad, &downstream);
+                });
+            }
+            Self::Test(pattern) => {
+                let videotestsrc_element = gst::ElementFactory::make("videotestsrc")
+                    .property("is-live", true)
+                    .build()
+                    .context("failed to create videotestsrc element")?;
+
+                if let Some(pattern) = pattern {
+                    let value = videotestsrc_element
+                        .find_property("pattern")
+                        .and_then(|pspec| glib::EnumClass::with_type(pspec.value_type()))
+                        .and_then(|class| class.to_value_by_nick(pattern))
+                        .with_context(|| format!("unknown videotestsrc pattern: {pattern}"))?;
+                    videotestsrc_element.set_property_from_value("pattern", &value);
+                }
+
+                pipeline.add(&videotestsrc_element)?;
+                videotestsrc_element.link(downstream)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// How fast a non-live source (i.e. a file) is played.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Pace {
+    /// Play the frames at the pace of their timestamps.
+    #[default]
+    Realtime,
+    /// Play the frames as fast as they can be decoded.
+    ///
+    /// The time-based sampling still follows the timestamps of the frames.
+    Fast,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(url: &str) -> anyhow::Result<Source> {
+        Source::try_from(url.to_string())
+    }
+
+    #[test]
+    fn parses_a_device_path() {
+        assert!(
+            matches!(parse("/dev/video0"), Ok(Source::V4l2(device)) if device == "/dev/video0")
+        );
+    }
+
+    #[test]
+    fn parses_a_v4l2_url() {
+        assert!(
+            matches!(parse("v4l2:///dev/video1"), Ok(Source::V4l2(device)) if device == "/dev/video1")
+        );
+    }
+
+    #[test]
+    fn parses_a_test_url_with_or_without_a_pattern() {
+        assert!(matches!(parse("test://"), Ok(Source::Test(None))));
+        assert!(
+            matches!(parse("test://ball"), Ok(Source::Test(Some(pattern))) if pattern == "ball")
+        );
+    }
+
+    #[test]
+    fn parses_a_file_url() {
+        assert!(
+            matches!(parse("file:///videos/a.mp4"), Ok(Source::File(path)) if path == "/videos/a.mp4")
+        );
+    }
+
+    #[test]
+    fn parses_rtsp_and_rtsps_urls() {
+        for url in ["rtsp://camera/stream", "rtsps://camera/stream"] {
+            assert!(matches!(parse(url), Ok(Source::Rtsp(parsed)) if parsed == url));
+        }
+    }
+
+    #[test]
+    fn rejects_a_url_without_a_scheme() {
+        let err = parse("camera/stream").unwrap_err();
+
+        assert!(err.to_string().contains("missing scheme"), "{err}");
+    }
+
+    #[test]
+    fn rejects_an_unsupported_scheme() {
+        let err = parse("ftp://camera/stream").unwrap_err();
+
+        assert!(err.to_string().contains("unsupported scheme"), "{err}");
+    }
+
+    #[test]
+    fn displays_the_source_as_its_url() {
+        for url in [
+            "rtsp://camera/stream",
+            "file:///videos/a.mp4",
+            "v4l2:///dev/video1",
+            "test://",
+            "test://ball",
+        ] {
+            assert_eq!(parse(url).unwrap().to_string(), url);
+        }
+    }
+}
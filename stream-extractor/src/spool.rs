@@ -0,0 +1,489 @@
+use std::{
+    collections::VecDeque,
+    io::BufRead as _,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::Context;
+use async_nats::{HeaderMap, connection::State};
+use bytes::Bytes;
+use iot_common::jetstream::Publisher;
+use tokio::sync::{Mutex, Notify};
+use tokio_util::sync::CancellationToken;
+
+use crate::metrics::Metrics;
+
+/// How long to wait before retrying to replay the spool.
+const RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The configuration of the local spool.
+///
+/// When NATS is unreachable, the encoded frames are kept on the local disk,
+/// and replayed in order once the connection returns.
+#[derive(Clone, Debug, serde::Deserialize)]
+#[serde(default)]
+pub struct SpoolConfig {
+    /// The directory of the spooled frames. The frames left by the previous runs are replayed.
+    pub directory: PathBuf,
+
+    /// The maximum size of the spool, in bytes. The oldest frames are dropped beyond it.
+    pub max_bytes: u64,
+
+    /// The maximum age of a spooled frame, in seconds. The older frames are dropped.
+    pub max_age_secs: u64,
+}
+
+impl Default for SpoolConfig {
+    fn default() -> Self {
+        Self {
+            directory: PathBuf::from("spool"),
+            max_bytes: 1024 * 1024 * 1024,
+            max_age_secs: 3600,
+        }
+    }
+}
+
+impl SpoolConfig {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.max_bytes == 0 {
+            anyhow::bail!("max_bytes should be greater than 0");
+        }
+        if self.max_age_secs == 0 {
+            anyhow::bail!("max_age_secs should be greater than 0");
+        }
+
+        Ok(())
+    }
+}
+
+/// A spooled frame on the disk.
+struct SpoolEntry {
+    path: PathBuf,
+    size: u64,
+    spooled_at: SystemTime,
+    /// Kept to count the evicted frames without reading them again.
+    monitor_id: Option<String>,
+}
+
+#[derive(Default)]
+struct SpoolState {
+    next_sequence: u64,
+    /// The spooled frames, oldest first.
+    entries: VecDeque<SpoolEntry>,
+    bytes: u64,
+}
+
+impl SpoolState {
+    /// Pick up the frames spooled in the directory by the previous runs, in the order they were spooled.
+    fn load(directory: &Path) -> anyhow::Result<Self> {
+        let mut entries = Vec::new();
+        for entry in std::fs::read_dir(directory)
+            .with_context(|| format!("Failed to read the spool directory {directory:?}"))?
+        {
+            let entry = entry.context("Failed to read the spool directory")?;
+            let path = entry.path();
+            let Some((sequence, spooled_at)) = parse_file_name(&path) else {
+                continue;
+            };
+            let size = entry.metadata().map(|metadata| metadata.len()).unwrap_or(0);
+            let monitor_id = read_monitor_id(&path);
+
+            entries.push((
+                sequence,
+                SpoolEntry {
+                    path,
+                    size,
+                    spooled_at,
+                    monitor_id,
+                },
+            ));
+        }
+        entries.sort_by_key(|(sequence, _)| *sequence);
+
+        Ok(Self {
+            next_sequence: entries.last().map_or(0, |(sequence, _)| sequence + 1),
+            bytes: entries.iter().map(|(_, entry)| entry.size).sum(),
+            entries: entries.into_iter().map(|(_, entry)| entry).collect(),
+        })
+    }
+}
+
+/// The headers of a spooled frame, written before its payload.
+#[derive(serde::Serialize, serde::Deserialize)]
+struct SpooledHeaders {
+    headers: Vec<(String, String)>,
+}
+
+/// The bounded on-disk spool of the frames waiting for NATS.
+pub struct Spool {
+    config: SpoolConfig,
+    max_age: Duration,
+    state: Mutex<SpoolState>,
+    pushed: Notify,
+    client: async_nats::Client,
+    metrics: Arc<Metrics>,
+}
+
+impl Spool {
+    /// Open the spool, picking up the frames left by the previous runs.
+    pub fn open(
+        config: SpoolConfig,
+        client: async_nats::Client,
+        metrics: Arc<Metrics>,
+    ) -> anyhow::Result<Self> {
+        std::fs::create_dir_all(&config.directory).with_context(|| {
+            format!(
+                "Failed to create the spool directory {:?}",
+                config.directory
+            )
+        })?;
+
+        let state = SpoolState::load(&config.directory)?;
+        if !state.entries.is_empty() {
+            tracing::info!(
+                "Found {} spooled frames from the previous runs",
+                state.entries.len()
+            );
+        }
+        metrics.spool_depth.set(state.entries.len() as i64);
+        metrics.spool_bytes.set(state.bytes as i64);
+
+        Ok(Self {
+            max_age: Duration::from_secs(config.max_age_secs),
+            config,
+            state: Mutex::new(state),
+            pushed: Notify::new(),
+            client,
+            metrics,
+        })
+    }
+
+    /// Whether a new frame should be spooled instead of published: NATS is unreachable,
+    /// or there are frames waiting to be replayed, which the new frame should not overtake.
+    pub async fn should_spool(&self) -> bool {
+        self.client.connection_state() != State::Connected
+            || !self.state.lock().await.entries.is_empty()
+    }
+
+    /// Spool a frame, dropping the oldest frames beyond the size of the spool.
+    pub async fn push(&self, headers: &HeaderMap, payload: &Bytes) -> anyhow::Result<()> {
+        let mut content = serde_json::to_vec(&SpooledHeaders {
+            headers: headers
+                .iter()
+                .flat_map(|(name, values)| {
+                    values
+                        .iter()
+                        .map(|value| (name.to_string(), value.to_string()))
+                })
+                .collect(),
+        })
+        .context("Failed to serialize the headers")?;
+        content.push(b'\n');
+        content.extend_from_slice(payload);
+
+        let mut state = self.state.lock().await;
+
+        let spooled_at = SystemTime::now();
+        let path = self
+            .config
+            .directory
+            .join(file_name(state.next_sequence, spooled_at));
+        tokio::fs::write(&path, &content)
+            .await
+            .with_context(|| format!("Failed to write the spooled frame {path:?}"))?;
+
+        state.next_sequence += 1;
+        state.bytes += content.len() as u64;
+        state.entries.push_back(SpoolEntry {
+            path,
+            size: content.len() as u64,
+            spooled_at,
+            monitor_id: headers
+                .get("Monitor-Id")
+                .map(|monitor_id| monitor_id.to_string()),
+        });
+
+        while state.bytes > self.config.max_bytes {
+            tracing::warn!("The spool is full; dropping the oldest frame.");
+            self.evict_front(&mut state).await;
+        }
+
+        self.update_metrics(&state);
+        drop(state);
+
+        self.pushed.notify_one();
+
+        Ok(())
+    }
+
+    /// Replay the spooled frames in order whenever NATS is connected, until the shutdown.
+    ///
+    /// The frames older than [`SpoolConfig::max_age_secs`] are dropped.
+    pub async fn replay(&self, publisher: Publisher, shutdown: CancellationToken) {
+        loop {
+            let mut state = self.state.lock().await;
+
+            let Some(entry) = state.entries.front() else {
+                drop(state);
+                tokio::select! {
+                    _ = self.pushed.notified() => continue,
+                    _ = shutdown.cancelled() => break,
+                }
+            };
+
+            if entry.spooled_at.elapsed().unwrap_or_default() > self.max_age {
+                tracing::warn!(
+                    "The spooled frame {:?} is expired; dropping it.",
+                    entry.path
+                );
+                self.evict_front(&mut state).await;
+                self.update_metrics(&state);
+                continue;
+            }
+
+            if self.client.connection_state() != State::Connected {
+                drop(state);
+                tokio::select! {
+                    _ = tokio::time::sleep(RETRY_INTERVAL) => continue,
+                    _ = shutdown.cancelled() => break,
+                }
+            }
+
+            let path = entry.path.clone();
+            let (mut headers, payload) = match read_frame(&path).await {
+                Ok(frame) => frame,
+                Err(e) => {
+                    tracing::error!("Failed to read the spooled frame: {:?}; dropping it.", e);
+                    self.evict_front(&mut state).await;
+                    self.update_metrics(&state);
+                    continue;
+                }
+            };
+            // Do not block the spooling of the new frames while publishing.
+            drop(state);
+
+            headers.insert("Published-At", chrono::Utc::now().to_rfc3339());
+            let monitor_id = headers
+                .get("Monitor-Id")
+                .map(|monitor_id| monitor_id.to_string())
+                .unwrap_or_default();
+
+            if let Err(e) = publisher.publish("frames", headers, payload).await {
+                tracing::warn!("Failed to replay the spooled frame: {:?}; retrying.", e);
+                tokio::select! {
+                    _ = tokio::time::sleep(RETRY_INTERVAL) => continue,
+                    _ = shutdown.cancelled() => break,
+                }
+            }
+
+            // The frame may have been evicted while publishing, in which case it is
+            // already counted as evicted.
+            let mut state = self.state.lock().await;
+            if state
+                .entries
+                .front()
+                .is_some_and(|entry| entry.path == path)
+                && let Some(entry) = state.entries.pop_front()
+            {
+                self.metrics.record_published(&monitor_id);
+                state.bytes -= entry.size;
+                if let Err(e) = tokio::fs::remove_file(&entry.path).await {
+                    tracing::warn!(
+                        "Failed to remove the spooled frame {:?}: {:?}",
+                        entry.path,
+                        e
+                    );
+                }
+            }
+            self.update_metrics(&state);
+        }
+    }
+
+    /// Drop the oldest frame.
+    async fn evict_front(&self, state: &mut SpoolState) {
+        let Some(entry) = state.entries.pop_front() else {
+            return;
+        };
+        state.bytes -= entry.size;
+
+        if let Some(monitor_id) = &entry.monitor_id {
+            self.metrics.record_spool_evicted(monitor_id);
+        }
+        if let Err(e) = tokio::fs::remove_file(&entry.path).await {
+            tracing::warn!(
+                "Failed to remove the spooled frame {:?}: {:?}",
+                entry.path,
+                e
+            );
+        }
+    }
+
+    fn update_metrics(&self, state: &SpoolState) {
+        self.metrics.spool_depth.set(state.entries.len() as i64);
+        self.metrics.spool_bytes.set(state.bytes as i64);
+    }
+}
+
+/// The file name of a spooled frame, `<sequence>-<unix_ms>.frame`.
+fn file_name(sequence: u64, spooled_at: SystemTime) -> String {
+    format!(
+        "{sequence:020}-{}.frame",
+        spooled_at
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis()
+    )
+}
+
+/// Parse the sequence and the spool time from `<sequence>-<unix_ms>.frame`.
+fn parse_file_name(path: &Path) -> Option<(u64, SystemTime)> {
+    if path.extension()? != "frame" {
+        return None;
+    }
+    let (sequence, spooled_at) = path.file_stem()?.to_str()?.split_once('-')?;
+
+    let spooled_at = SystemTime::UNIX_EPOCH + Duration::from_millis(spooled_at.parse().ok()?);
+    Some((sequence.parse().ok()?, spooled_at))
+}
+
+/// Read the `Monitor-Id` header from the first line of a spooled frame.
+fn read_monitor_id(path: &Path) -> Option<String> {
+    let file = std::fs::File::open(path).ok()?;
+    let mut line = Vec::new();
+    std::io::BufReader::new(file)
+        .read_until(b'\n', &mut line)
+        .ok()?;
+
+    let spooled: SpooledHeaders = serde_json::from_slice(line.strip_suffix(b"\n")?).ok()?;
+    spooled
+        .headers
+        .into_iter()
+        .find(|(name, _)| name == "Monitor-Id")
+        .map(|(_, monitor_id)| monitor_id)
+}
+
+async fn read_frame(path: &Path) -> anyhow::Result<(HeaderMap, Bytes)> {
+    let content = tokio::fs::read(path)
+        .await
+        .with_context(|| format!("Failed to read {path:?}"))?;
+
+    let separator = content
+        .iter()
+        .position(|&byte| byte == b'\n')
+        .context("The spooled frame has no headers")?;
+    let spooled: SpooledHeaders = serde_json::from_slice(&content[..separator])
+        .context("Failed to parse the headers of the spooled frame")?;
+
+    let mut headers = HeaderMap::new();
+    for (name, value) in spooled.headers {
+        headers.append(name.as_str(), value);
+    }
+
+    let payload = Bytes::copy_from_slice(&content[separator + 1..]);
+    Ok((headers, payload))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A spool directory removed on drop.
+    struct TempDirectory(PathBuf);
+
+    impl TempDirectory {
+        fn new() -> Self {
+            let path = std::env::temp_dir().join(format!("spool-{}", uuid::Uuid::now_v7()));
+            std::fs::create_dir_all(&path).unwrap();
+            Self(path)
+        }
+
+        /// Spool a frame of the monitor as a run would.
+        fn spool(&self, sequence: u64, spooled_at: SystemTime, monitor_id: &str) {
+            let headers = SpooledHeaders {
+                headers: vec![("Monitor-Id".to_string(), monitor_id.to_string())],
+            };
+            let mut content = serde_json::to_vec(&headers).unwrap();
+            content.push(b'\n');
+            content.extend_from_slice(b"frame");
+
+            std::fs::write(self.0.join(file_name(sequence, spooled_at)), content).unwrap();
+        }
+    }
+
+    impl Drop for TempDirectory {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn at(ms: u64) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn parses_the_file_names() {
+        let name = file_name(42, at(1_700_000_000_123));
+        assert_eq!(name, "00000000000000000042-1700000000123.frame");
+        assert_eq!(
+            parse_file_name(Path::new(&name)),
+            Some((42, at(1_700_000_000_123)))
+        );
+
+        assert_eq!(
+            parse_file_name(Path::new("00000000000000000042-1.tmp")),
+            None
+        );
+        assert_eq!(parse_file_name(Path::new("42.frame")), None);
+        assert_eq!(parse_file_name(Path::new("a-1.frame")), None);
+        assert_eq!(parse_file_name(Path::new("42-a.frame")), None);
+    }
+
+    #[test]
+    fn replays_the_previous_runs_in_order() {
+        let directory = TempDirectory::new();
+
+        // the second run spooled later, with a clock set backwards
+        for sequence in 3..12 {
+            directory.spool(sequence, at(1_000 + sequence), "back-door");
+        }
+        for sequence in 0..3 {
+            directory.spool(sequence, at(5_000 + sequence), "front-door");
+        }
+        std::fs::write(directory.0.join("notes.txt"), "not a frame").unwrap();
+
+        let state = SpoolState::load(&directory.0).unwrap();
+
+        let sequences = state
+            .entries
+            .iter()
+            .map(|entry| parse_file_name(&entry.path).unwrap().0)
+            .collect::<Vec<_>>();
+        assert_eq!(sequences, (0..12).collect::<Vec<_>>());
+        assert_eq!(state.next_sequence, 12);
+        assert_eq!(
+            state.bytes,
+            state.entries.iter().map(|entry| entry.size).sum::<u64>()
+        );
+
+        let monitor_ids = state
+            .entries
+            .iter()
+            .map(|entry| entry.monitor_id.as_deref())
+            .collect::<Vec<_>>();
+        assert_eq!(monitor_ids[..3], [Some("front-door"); 3]);
+        assert_eq!(monitor_ids[3..], [Some("back-door"); 9]);
+    }
+
+    #[test]
+    fn starts_empty_without_previous_runs() {
+        let directory = TempDirectory::new();
+
+        let state = SpoolState::load(&directory.0).unwrap();
+
+        assert!(state.entries.is_empty());
+        assert_eq!(state.next_sequence, 0);
+        assert_eq!(state.bytes, 0);
+    }
+}
@@ -0,0 +1,480 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime},
+};
+
+use image::{DynamicImage, GrayImage, imageops::FilterType};
+use tokio::sync::mpsc;
+
+use crate::worker::ExtractedFrame;
+
+/// The width of the downscaled frame to detect tampering on.
+const TAMPER_FRAME_WIDTH: u32 = 160;
+
+/// The width of the downscaled frame to estimate the shift of the view on.
+const SHIFT_FRAME_WIDTH: u32 = 80;
+
+/// The configuration of the tamper detection.
+///
+/// The sampled frames are compared against a reference view of the monitor,
+/// learned from the frames that are not tampered with.
+#[derive(Clone, Debug, serde::Deserialize)]
+#[serde(default)]
+pub struct TamperConfig {
+    /// The minimum standard deviation of the grayscale frame, from 0 to 255.
+    /// A flatter frame is covered, or blacked out if it is also dark.
+    pub min_stddev: f32,
+
+    /// The maximum mean of the grayscale frame, from 0 to 255, for a flat frame to be blacked out.
+    pub dark_mean: f32,
+
+    /// The maximum ratio of the pixels changed from the reference view, from 0.0 to 1.0,
+    /// before the scene is considered replaced.
+    pub max_scene_change: f32,
+
+    /// The minimum ratio of the sharpness to the one of the reference view, from 0.0 to 1.0,
+    /// before the frame is considered defocused.
+    pub min_sharpness: f32,
+
+    /// The maximum shift of the view from the reference view, as a ratio of the width,
+    /// before the camera is considered moved.
+    pub max_shift: f32,
+
+    /// How many consecutive sampled frames must be tampered with to raise an event.
+    pub min_frames: u32,
+
+    /// How fast the reference view adapts to the frames that are not tampered with, from 0.0 to 1.0.
+    pub reference_rate: f32,
+
+    /// Learn a new reference view if the tampering lasts for this long, in seconds,
+    /// e.g. after the camera is moved on purpose.
+    pub relearn_after_secs: u64,
+}
+
+impl Default for TamperConfig {
+    fn default() -> Self {
+        Self {
+            min_stddev: 8.0,
+            dark_mean: 30.0,
+            max_scene_change: 0.7,
+            min_sharpness: 0.3,
+            max_shift: 0.1,
+            min_frames: 3,
+            reference_rate: 0.02,
+            relearn_after_secs: 600,
+        }
+    }
+}
+
+impl TamperConfig {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        for (name, value) in [
+            ("min_stddev", self.min_stddev),
+            ("dark_mean", self.dark_mean),
+        ] {
+            if !(0.0..=255.0).contains(&value) {
+                anyhow::bail!("tamper.{name} should be in the range of 0 to 255");
+            }
+        }
+        for (name, value) in [
+            ("max_scene_change", self.max_scene_change),
+            ("min_sharpness", self.min_sharpness),
+            ("reference_rate", self.reference_rate),
+        ] {
+            if !(0.0..=1.0).contains(&value) {
+                anyhow::bail!("tamper.{name} should be in the range of 0.0 to 1.0");
+            }
+        }
+        if !(0.0..0.5).contains(&self.max_shift) {
+            anyhow::bail!("tamper.max_shift should be in the range of 0.0 to 0.5");
+        }
+        if self.min_frames == 0 {
+            anyhow::bail!("tamper.min_frames should be greater than 0");
+        }
+
+        Ok(())
+    }
+}
+
+/// How a monitor is tampered with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TamperKind {
+    /// The lens is covered, e.g. spray-painted.
+    Covered,
+
+    /// The frame is (almost) black.
+    BlackedOut,
+
+    /// The frame lost its sharpness.
+    Defocused,
+
+    /// The view is shifted, e.g. the camera is turned away.
+    Moved,
+
+    /// Most of the scene changed at once.
+    SceneChanged,
+}
+
+impl TamperKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Covered => "covered",
+            Self::BlackedOut => "blacked_out",
+            Self::Defocused => "defocused",
+            Self::Moved => "moved",
+            Self::SceneChanged => "scene_changed",
+        }
+    }
+}
+
+/// A tamper event, raised once per incident.
+pub struct TamperEvent {
+    pub kind: TamperKind,
+    pub frame: ExtractedFrame,
+}
+
+/// Detect tampering on the sampled frames of a monitor, and raise the events.
+///
+/// The reference view is kept across the runs of the pipeline, so a camera moved
+/// while the stream is down is detected too. The [`Clone`] operation is cheap.
+#[derive(Clone)]
+pub struct TamperDetector {
+    config: Arc<TamperConfig>,
+    state: Arc<Mutex<TamperState>>,
+    events: mpsc::Sender<TamperEvent>,
+}
+
+#[derive(Default)]
+struct TamperState {
+    reference: Option<Reference>,
+    /// The consecutive tampered frames.
+    tampered_frames: u32,
+    /// When the tampering started.
+    tampered_since: Option<Instant>,
+    /// Whether the event of the ongoing tampering is raised.
+    raised: bool,
+}
+
+/// The reference view of the monitor.
+struct Reference {
+    gray: Vec<f32>,
+    shift: Vec<f32>,
+    sharpness: f32,
+}
+
+impl TamperDetector {
+    pub fn new(config: TamperConfig, events: mpsc::Sender<TamperEvent>) -> Self {
+        Self {
+            config: Arc::new(config),
+            state: Arc::default(),
+            events,
+        }
+    }
+
+    /// Check the frame for tampering, and raise an event once it is tampered with
+    /// for [`TamperConfig::min_frames`] consecutive frames.
+    pub fn inspect(&self, frame: &DynamicImage, captured_at: SystemTime) {
+        let config = &self.config;
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+
+        let gray = downscale(frame, TAMPER_FRAME_WIDTH);
+        let shift = downscale(frame, SHIFT_FRAME_WIDTH);
+        let tamper = detect(config, state.reference.as_ref(), &gray, &shift);
+
+        let Some(kind) = tamper else {
+            if state.raised {
+                tracing::info!("The monitor is no longer tampered with.");
+            }
+            state.tampered_frames = 0;
+            state.tampered_since = None;
+            state.raised = false;
+
+            // Learn the reference view from the frames that are not tampered with.
+            let sharpness = sharpness(&gray);
+            let (gray, shift) = (to_f32(&gray), to_f32(&shift));
+            match &mut state.reference {
+                Some(reference) if reference.gray.len() == gray.len() => {
+                    let rate = config.reference_rate;
+                    blend(&mut reference.gray, &gray, rate);
+                    blend(&mut reference.shift, &shift, rate);
+                    reference.sharpness += (sharpness - reference.sharpness) * rate;
+                }
+                // The first frame (or the resolution changed): use it as the reference.
+                reference => {
+                    *reference = Some(Reference {
+                        gray,
+                        shift,
+                        sharpness,
+                    });
+                }
+            }
+            return;
+        };
+
+        state.tampered_frames += 1;
+        let tampered_since = *state.tampered_since.get_or_insert_with(Instant::now);
+
+        if !state.raised && state.tampered_frames >= config.min_frames {
+            state.raised = true;
+            tracing::warn!("The monitor is tampered with: {}", kind.as_str());
+
+            let event = TamperEvent {
+                kind,
+                frame: ExtractedFrame::new(captured_at, frame.clone()),
+            };
+            if let Err(e) = self.events.try_send(event) {
+                tracing::warn!("Failed to raise the tamper event: {e}; skipping.");
+            }
+        }
+
+        if tampered_since.elapsed() >= Duration::from_secs(config.relearn_after_secs) {
+            tracing::info!("The tampering lasts too long; learning a new reference view.");
+            // Keep the event raised until the monitor recovers, so a lens that stays covered
+            // is not alerted again after each relearning.
+            state.reference = None;
+            state.tampered_since = None;
+        }
+    }
+}
+
+/// Find how the frame is tampered with, if it is.
+fn detect(
+    config: &TamperConfig,
+    reference: Option<&Reference>,
+    gray: &GrayImage,
+    shift: &GrayImage,
+) -> Option<TamperKind> {
+    let pixels = gray.as_raw();
+    let count = pixels.len().max(1) as f32;
+    let mean = pixels.iter().map(|&p| p as f32).sum::<f32>() / count;
+    let variance = pixels
+        .iter()
+        .map(|&p| (p as f32 - mean).powi(2))
+        .sum::<f32>()
+        / count;
+
+    if variance.sqrt() < config.min_stddev {
+        return Some(if mean <= config.dark_mean {
+            TamperKind::BlackedOut
+        } else {
+            TamperKind::Covered
+        });
+    }
+
+    let reference = reference.filter(|reference| reference.gray.len() == pixels.len())?;
+
+    // Check the shift before the scene change, as a moved view changes most of the pixels too.
+    let (dx, dy, shifted_error, error) = estimate_shift(
+        &reference.shift,
+        shift,
+        (config.max_shift * 2.0 * SHIFT_FRAME_WIDTH as f32).ceil() as i32,
+    );
+    let max_offset = config.max_shift * shift.width() as f32;
+    if dx.abs().max(dy.abs()) as f32 >= max_offset && shifted_error < error * 0.5 {
+        return Some(TamperKind::Moved);
+    }
+
+    let changed = pixels
+        .iter()
+        .zip(&reference.gray)
+        .filter(|&(&p, &r)| (p as f32 - r).abs() > 40.0)
+        .count() as f32;
+    if changed / count > config.max_scene_change {
+        return Some(TamperKind::SceneChanged);
+    }
+
+    // A flat reference view (e.g. at night) has no sharpness to lose.
+    if reference.sharpness > 10.0 && sharpness(gray) < reference.sharpness * config.min_sharpness {
+        return Some(TamperKind::Defocused);
+    }
+
+    None
+}
+
+/// Find the offset of the frame from the reference within `radius` pixels.
+///
+/// Returns the offset, the mean absolute error at the offset, and the one without offset.
+fn estimate_shift(reference: &[f32], frame: &GrayImage, radius: i32) -> (i32, i32, f32, f32) {
+    let (width, height) = (frame.width() as i32, frame.height() as i32);
+    let radius = radius.min(width / 2).min(height / 2);
+    let pixels = frame.as_raw();
+
+    let error_at = |dx: i32, dy: i32| {
+        let mut sum = 0.0;
+        let mut count = 0usize;
+        for y in radius..height - radius {
+            for x in radius..width - radius {
+                let p = pixels[((y + dy) * width + x + dx) as usize] as f32;
+                sum += (p - reference[(y * width + x) as usize]).abs();
+                count += 1;
+            }
+        }
+        sum / count.max(1) as f32
+    };
+
+    let error = error_at(0, 0);
+    let mut best = (0, 0, error);
+    for dy in -radius..=radius {
+        for dx in -radius..=radius {
+            let shifted_error = error_at(dx, dy);
+            if shifted_error < best.2 {
+                best = (dx, dy, shifted_error);
+            }
+        }
+    }
+
+    (best.0, best.1, best.2, error)
+}
+
+/// The variance of the Laplacian of the frame.
+fn sharpness(gray: &GrayImage) -> f32 {
+    let (width, height) = gray.dimensions();
+    if width < 3 || height < 3 {
+        return 0.0;
+    }
+
+    let pixel = |x: u32, y: u32| gray.get_pixel(x, y).0[0] as f32;
+    let laplacian = (1..height - 1)
+        .flat_map(|y| (1..width - 1).map(move |x| (x, y)))
+        .map(|(x, y)| {
+            pixel(x - 1, y) + pixel(x + 1, y) + pixel(x, y - 1) + pixel(x, y + 1)
+                - 4.0 * pixel(x, y)
+        })
+        .collect::<Vec<_>>();
+
+    let count = laplacian.len() as f32;
+    let mean = laplacian.iter().sum::<f32>() / count;
+    laplacian.iter().map(|l| (l - mean).powi(2)).sum::<f32>() / count
+}
+
+fn downscale(frame: &DynamicImage, width: u32) -> GrayImage {
+    let height = (frame.height() * width / frame.width().max(1)).max(1);
+    frame
+        .resize_exact(width, height, FilterType::Triangle)
+        .into_luma8()
+}
+
+fn to_f32(gray: &GrayImage) -> Vec<f32> {
+    gray.as_raw().iter().map(|&p| p as f32).collect()
+}
+
+fn blend(reference: &mut [f32], frame: &[f32], rate: f32) {
+    for (reference, pixel) in reference.iter_mut().zip(frame) {
+        *reference += (pixel - *reference) * rate;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Luma, RgbImage};
+
+    use super::*;
+
+    /// A textured frame of 8x8 blocks, offset by `(dx, dy)` pixels.
+    fn textured_frame(dx: u32, dy: u32) -> DynamicImage {
+        let frame = GrayImage::from_fn(320, 240, |x, y| {
+            let (bx, by) = ((x + 320 - dx) / 8, (y + 240 - dy) / 8);
+            Luma([(bx.wrapping_mul(73) ^ by.wrapping_mul(151)).wrapping_mul(37) as u8])
+        });
+        DynamicImage::ImageLuma8(frame)
+    }
+
+    fn flat_frame(value: u8) -> DynamicImage {
+        DynamicImage::ImageRgb8(RgbImage::from_pixel(320, 240, image::Rgb([value; 3])))
+    }
+
+    fn views(frame: &DynamicImage) -> (GrayImage, GrayImage) {
+        (
+            downscale(frame, TAMPER_FRAME_WIDTH),
+            downscale(frame, SHIFT_FRAME_WIDTH),
+        )
+    }
+
+    fn reference(frame: &DynamicImage) -> Reference {
+        let (gray, shift) = views(frame);
+        Reference {
+            sharpness: sharpness(&gray),
+            gray: to_f32(&gray),
+            shift: to_f32(&shift),
+        }
+    }
+
+    fn detect_frame(reference: Option<&Reference>, frame: &DynamicImage) -> Option<TamperKind> {
+        let (gray, shift) = views(frame);
+        detect(&TamperConfig::default(), reference, &gray, &shift)
+    }
+
+    #[test]
+    fn detects_a_black_frame() {
+        assert_eq!(
+            detect_frame(None, &flat_frame(5)),
+            Some(TamperKind::BlackedOut)
+        );
+    }
+
+    #[test]
+    fn detects_a_covered_frame() {
+        assert_eq!(
+            detect_frame(None, &flat_frame(128)),
+            Some(TamperKind::Covered)
+        );
+    }
+
+    #[test]
+    fn accepts_the_reference_view() {
+        let frame = textured_frame(0, 0);
+
+        assert_eq!(detect_frame(Some(&reference(&frame)), &frame), None);
+    }
+
+    #[test]
+    fn detects_a_shifted_frame() {
+        let reference = reference(&textured_frame(0, 0));
+
+        // 48px of 320 is 15% of the width, beyond the max shift of 10%
+        let moved = textured_frame(48, 0);
+
+        assert_eq!(
+            detect_frame(Some(&reference), &moved),
+            Some(TamperKind::Moved)
+        );
+    }
+
+    #[test]
+    fn raises_a_single_event_while_the_lens_stays_covered() {
+        let (events, mut receiver) = mpsc::channel(16);
+        let detector = TamperDetector::new(
+            TamperConfig {
+                min_frames: 1,
+                relearn_after_secs: 0,
+                ..TamperConfig::default()
+            },
+            events,
+        );
+
+        detector.inspect(&textured_frame(0, 0), SystemTime::now());
+        for _ in 0..5 {
+            detector.inspect(&flat_frame(128), SystemTime::now());
+        }
+        assert_eq!(receiver.try_recv().unwrap().kind, TamperKind::Covered);
+        assert!(receiver.try_recv().is_err());
+
+        // once recovered, the next tampering raises an event again
+        detector.inspect(&textured_frame(0, 0), SystemTime::now());
+        detector.inspect(&flat_frame(5), SystemTime::now());
+        assert_eq!(receiver.try_recv().unwrap().kind, TamperKind::BlackedOut);
+    }
+
+    #[test]
+    fn estimates_the_shift_of_the_frame() {
+        let (_, shift) = views(&textured_frame(0, 0));
+        let (_, shifted) = views(&textured_frame(8, 16));
+
+        let (dx, dy, shifted_error, error) = estimate_shift(&to_f32(&shift), &shifted, 16);
+
+        assert_eq!((dx, dy), (2, 4));
+        assert!(shifted_error < 1.0);
+        assert!(error > shifted_error);
+    }
+}
@@ -1,53 +1,187 @@
 use anyhow::Context;
+use chrono::{DateTime, Utc};
 use glib::object::Cast;
 use gst::prelude::*;
-use gstreamer::prelude::ElementExt;
 use gstreamer::{self as gst, Pipeline};
 use gstreamer_app::AppSinkCallbacks;
-use gstreamer_video as gst_video;
+use gstreamer_video::{self as gst_video, VideoFrameExt};
 use image::{DynamicImage, RgbImage};
 use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use tokio::sync::watch;
+use uuid::{NoContext, Timestamp, Uuid};
+
+use crate::announce::StreamInfo;
+use crate::codec::Codec;
+use crate::mask::PrivacyMasks;
+use crate::metrics::MonitorMetrics;
+use crate::motion::{MotionConfig, MotionDetector};
+use crate::queue::FrameSender;
+use crate::recorder::Recorder;
+use crate::sampler::{Sampler, Sampling};
+use crate::source::{Pace, Source};
+use crate::tamper::TamperDetector;
+
+/// The NTP epoch (1900-01-01) in the Unix time.
+const NTP_UNIX_OFFSET_SECS: u64 = 2_208_988_800;
+
+/// A frame extracted from the stream.
+pub struct ExtractedFrame {
+    /// The globally unique and time-sortable ID (UUIDv7) of the frame.
+    pub id: Uuid,
+
+    /// The time when the frame was captured.
+    pub captured_at: DateTime<Utc>,
+
+    pub image: DynamicImage,
+}
+
+impl ExtractedFrame {
+    /// Create a frame with a new ID generated from the capture time.
+    pub fn new(captured_at: SystemTime, image: DynamicImage) -> Self {
+        let since_epoch = captured_at
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default();
+
+        Self {
+            id: Uuid::new_v7(Timestamp::from_unix(
+                NoContext,
+                since_epoch.as_secs(),
+                since_epoch.subsec_nanos(),
+            )),
+            captured_at: captured_at.into(),
+            image,
+        }
+    }
+}
+
+/// The latest decoded frame of a monitor, kept for the snapshots.
+///
+/// Only the reference to the decoded buffer is kept; it is converted
+/// to a masked image on demand. The [`Clone`] operation is cheap.
+#[derive(Clone)]
+pub struct LatestFrame {
+    frame: Arc<Mutex<Option<DecodedFrame>>>,
+    masks: PrivacyMasks,
+}
+
+#[derive(Clone)]
+struct DecodedFrame {
+    sample: gst::Sample,
+    running_time: Option<gst::ClockTime>,
+    base_time: Option<gst::ClockTime>,
+}
+
+impl LatestFrame {
+    pub fn new(masks: PrivacyMasks) -> Self {
+        Self {
+            frame: Arc::default(),
+            masks,
+        }
+    }
+
+    fn set(&self, frame: DecodedFrame) {
+        *self.frame.lock().unwrap_or_else(|e| e.into_inner()) = Some(frame);
+    }
+
+    /// Convert the latest decoded frame to an image.
+    ///
+    /// Returns `None` if no frame has been decoded yet.
+    pub fn extract(&self) -> anyhow::Result<Option<ExtractedFrame>> {
+        let Some(DecodedFrame {
+            sample,
+            running_time,
+            base_time,
+        }) = self.frame.lock().unwrap_or_else(|e| e.into_inner()).clone()
+        else {
+            return Ok(None);
+        };
+
+        let (Some(buffer), Some(caps)) = (sample.buffer(), sample.caps()) else {
+            anyhow::bail!("the sample has no buffer or caps");
+        };
+        let video_info = gst_video::VideoInfo::from_caps(caps)
+            .with_context(|| format!("invalid caps {caps}"))?;
+        let image = buffer_to_image(buffer, &video_info, &self.masks)?;
+        let captured_at =
+            capture_time(buffer, running_time, base_time).unwrap_or_else(SystemTime::now);
+
+        Ok(Some(ExtractedFrame::new(captured_at, image)))
+    }
+}
 
 /// The builder of the extractor worker pipeline.
 pub struct ExtractorWorkerBuilder {
-    pub rtsp_url: String,
+    /// The input source of the frames.
+    pub source: Source,
+
+    /// The video codec of the RTSP stream.
+    ///
+    /// [`Codec::Auto`] negotiates the depayloader and decoder from the caps of the stream.
+    /// It is ignored by the other sources.
+    pub codec: Codec,
+
+    /// How fast a file source is played.
+    pub pace: Pace,
 
     /// The sender to the queue to NATS.
     ///
-    /// The first element of the tuple is the frame counter, and the second element is the frame.
-    pub sender: crossbeam::channel::Sender<(usize, DynamicImage)>,
+    /// When the queue is full, frames are dropped according to its overflow policy.
+    pub sender: FrameSender,
 
-    /// Specifies the interval at which frames are dispatched to the inference queue.
+    /// Specifies how frames are dispatched to the inference queue.
     ///
     /// This parameter helps manage the load on the inference queue by sending
-    /// frames only after a specified number of frames have been processed.
-    /// The default is set to dispatch a frame every 300 frames (10s at 30fps, 5s at 60fps).
-    pub frame_interval: Option<usize>,
+    /// only a part of the decoded frames, either every N frames or every N milliseconds
+    /// according to the buffer timestamps.
+    pub sampling: Sampling,
+
+    /// The motion gate of the sampled frames.
+    ///
+    /// If set, only the sampled frames with motion (and the keep-alive frames)
+    /// are dispatched to the inference queue.
+    pub motion: Option<MotionConfig>,
+
+    /// The maximum width of the extracted frames.
+    ///
+    /// Larger frames are downscaled in the pipeline, keeping the aspect ratio.
+    pub max_width: Option<u32>,
+
+    /// The maximum height of the extracted frames.
+    ///
+    /// Larger frames are downscaled in the pipeline, keeping the aspect ratio.
+    pub max_height: Option<u32>,
+
+    /// The regions masked in the extracted frames, before the motion gate.
+    pub masks: PrivacyMasks,
+
+    /// The metrics of the monitor.
+    pub metrics: MonitorMetrics,
+
+    /// Where the latest decoded frame is kept for the snapshots.
+    pub latest_frame: LatestFrame,
+
+    /// The rolling recording of the encoded stream, if enabled.
+    pub recorder: Option<Recorder>,
+
+    /// The tamper detection of the sampled frames, before the motion gate.
+    pub tamper: Option<TamperDetector>,
+
+    /// Where the resolution and the frame rate of the decoded stream are reported.
+    pub stream_info: watch::Sender<StreamInfo>,
 }
 
 impl ExtractorWorkerBuilder {
     pub fn build(self) -> anyhow::Result<Pipeline> {
         let pipeline = gstreamer::Pipeline::new();
-        let frame_interval = self.frame_interval.unwrap_or(300);
 
-        let rtspsrc_element = gst::ElementFactory::make("rtspsrc")
-            .property("location", self.rtsp_url)
-            .build()
-            .context("failed to create rtspsrc element")?;
-
-        let rtpjitterbuffer_element = gst::ElementFactory::make("rtpjitterbuffer")
-            .build()
-            .context("failed to create rtpjitterbuffer element")?;
-
-        let rtph264depay_element = gst::ElementFactory::make("rtph264depay")
-            .property("wait-for-keyframe", true)
-            .property("request-keyframe", true)
-            .build()
-            .context("failed to create rtph264depay element")?;
-
-        let avdec_h264_element = gst::ElementFactory::make("avdec_h264")
-            .build()
-            .context("failed to create avdec_h264 element")?;
+        // Use the wall clock as the pipeline clock, so that the running time
+        // of a frame can be converted to the time it was captured.
+        let realtime_clock = glib::Object::builder::<gst::SystemClock>()
+            .property("clock-type", gst::ClockType::Realtime)
+            .build();
+        pipeline.use_clock(Some(&realtime_clock));
 
         let videoconvert_element = gst::ElementFactory::make("videoconvert")
             .build()
@@ -59,7 +193,43 @@ impl ExtractorWorkerBuilder {
             .build()
             .context("failed to create identity element")?;
 
+        // Downscale the frames to the maximum resolution before converting them to RGB.
+        let scale_elements = if self.max_width.is_some() || self.max_height.is_some() {
+            let videoscale_element = gst::ElementFactory::make("videoscale")
+                .build()
+                .context("failed to create videoscale element")?;
+
+            // videoscale picks the largest size in the range that keeps the display aspect ratio.
+            let capsfilter_element = gst::ElementFactory::make("capsfilter")
+                .property(
+                    "caps",
+                    gst::Caps::builder("video/x-raw")
+                        .field(
+                            "width",
+                            gst::IntRange::new(1, max_dimension(self.max_width)),
+                        )
+                        .field(
+                            "height",
+                            gst::IntRange::new(1, max_dimension(self.max_height)),
+                        )
+                        .field("pixel-aspect-ratio", gst::Fraction::new(1, 1))
+                        .build(),
+                )
+                .build()
+                .context("failed to create capsfilter element")?;
+
+            vec![videoscale_element, capsfilter_element]
+        } else {
+            vec![]
+        };
+
+        // The frames of a file played fast run ahead of the pipeline clock, so they
+        // are timestamped with the wall clock on decoding instead.
+        let on_pipeline_clock = self.pace == Pace::Realtime;
+
         let frame_counter = AtomicUsize::new(0);
+        let mut sampler = Sampler::new(self.sampling);
+        let mut motion_detector = self.motion.as_ref().map(MotionDetector::new);
 
         let appsink_callback = AppSinkCallbacks::builder()
             .new_sample(move |sink| {
@@ -69,32 +239,65 @@ impl ExtractorWorkerBuilder {
                 };
 
                 // Extract the buffer and caps (metadata)
-                let buffer = sample.buffer().unwrap();
-                let caps = sample.caps().unwrap();
-                let video_info = gst_video::VideoInfo::from_caps(caps).unwrap();
-
-                // Convert the buffer to a readable format
-                let map = buffer.map_readable().unwrap();
+                let (Some(buffer), Some(caps)) = (sample.buffer(), sample.caps()) else {
+                    tracing::warn!("Received a sample without buffer or caps; skipping.");
+                    return Ok(gst::FlowSuccess::Ok);
+                };
+                let Ok(video_info) = gst_video::VideoInfo::from_caps(caps) else {
+                    tracing::warn!("Received a sample with invalid caps {caps}; skipping.");
+                    return Ok(gst::FlowSuccess::Ok);
+                };
 
                 // Increment the frame counter
                 let counter = frame_counter.fetch_add(1, Ordering::Relaxed);
+                self.metrics.record_decoded();
 
-                // Save frame as WebP every second (assuming 1 frame per second)
-                if counter % frame_interval == 0 {
-                    let width = video_info.width() as usize;
-                    let height = video_info.height() as usize;
+                // The running time of the frame, taken from the buffer PTS,
+                // or the pipeline clock if the buffer has no timestamp.
+                let running_time = buffer
+                    .pts()
+                    .zip(sample.segment())
+                    .and_then(|(pts, segment)| {
+                        segment
+                            .downcast_ref::<gst::format::Time>()?
+                            .to_running_time(pts)
+                    })
+                    .or_else(|| sink.current_running_time());
 
-                    // Extract the frame data
-                    let frame_data = map.as_slice();
+                let base_time = sink.base_time().filter(|_| on_pipeline_clock);
 
-                    let frame =
-                        RgbImage::from_raw(width as u32, height as u32, frame_data.to_vec())
-                            .expect("expect a valid image");
-                    let dynamic_image = DynamicImage::ImageRgb8(frame);
+                self.latest_frame.set(DecodedFrame {
+                    sample: sample.clone(),
+                    running_time,
+                    base_time,
+                });
 
+                if sampler.should_sample(running_time) {
+                    let dynamic_image = match buffer_to_image(buffer, &video_info, &self.masks) {
+                        Ok(image) => image,
+                        Err(e) => {
+                            tracing::warn!("Failed to convert frame {counter}: {e}; skipping.");
+                            return Ok(gst::FlowSuccess::Ok);
+                        }
+                    };
+
+                    let captured_at = capture_time(buffer, running_time, base_time)
+                        .unwrap_or_else(SystemTime::now);
+
+                    if let Some(tamper) = &self.tamper {
+                        tamper.inspect(&dynamic_image, captured_at);
+                    }
+
+                    if let Some(motion_detector) = &mut motion_detector
+                        && !motion_detector.should_dispatch(&dynamic_image)
+                    {
+                        tracing::debug!("No motion in frame {counter}; skipping.");
+                        return Ok(gst::FlowSuccess::Ok);
+                    }
+
+                    self.metrics.frames_sampled.inc();
                     self.sender
-                        .send((counter, dynamic_image))
-                        .expect("failed to send frame to inferrence queue");
+                        .send(ExtractedFrame::new(captured_at, dynamic_image));
                 }
 
                 Ok(gst::FlowSuccess::Ok)
@@ -103,7 +306,7 @@ impl ExtractorWorkerBuilder {
 
         let appsink_element = gstreamer_app::AppSink::builder()
             .name("appsink")
-            .sync(true)
+            .sync(self.pace == Pace::Realtime)
             .callbacks(appsink_callback)
             .caps(
                 &gst::Caps::builder("video/x-raw")
@@ -113,37 +316,105 @@ impl ExtractorWorkerBuilder {
             .build()
             .upcast();
 
-        pipeline.add_many([
-            &rtspsrc_element,
-            &rtpjitterbuffer_element,
-            &rtph264depay_element,
-            &avdec_h264_element,
-            &videoconvert_element,
-            &identity_element,
-            &appsink_element,
-        ])?;
-
-        let rtpjitterbuffer_element_clone = rtpjitterbuffer_element.clone();
-        rtspsrc_element.connect_pad_added(move |_, src_pad| {
-            let sink_pad = rtpjitterbuffer_element_clone.static_pad("sink").unwrap();
-            if !sink_pad.is_linked() {
-                match src_pad.link(&sink_pad) {
-                    Ok(_) => tracing::info!("Successfully linked pads"),
-                    Err(err) => tracing::warn!("Failed to link pads: {:?}", err),
+        let elements = scale_elements
+            .into_iter()
+            .chain([videoconvert_element, identity_element, appsink_element])
+            .collect::<Vec<_>>();
+
+        // Report the decoded stream before it is downscaled.
+        if let Some(sink_pad) = elements[0].static_pad("sink") {
+            let stream_info = self.stream_info.clone();
+            sink_pad.connect_notify(Some("caps"), move |pad, _| {
+                if let Some(caps) = pad.current_caps() {
+                    stream_info.send_if_modified(|info| info.update_from_caps(&caps));
                 }
-            }
-        });
+            });
+        }
+
+        pipeline.add_many(&elements)?;
+
+        let recorder_element = self
+            .recorder
+            .as_ref()
+            .map(|recorder| recorder.build(&pipeline))
+            .transpose()
+            .context("failed to create recorder elements")?;
+
+        self.source
+            .build(
+                &pipeline,
+                self.codec,
+                &elements[0],
+                recorder_element.as_ref(),
+            )
+            .context("failed to create source elements")?;
 
         // link elements
-        gst::Element::link_many([
-            &rtpjitterbuffer_element,
-            &rtph264depay_element,
-            &avdec_h264_element,
-            &videoconvert_element,
-            &identity_element,
-            &appsink_element,
-        ])?;
+        gst::Element::link_many(&elements)?;
 
         Ok(pipeline)
     }
 }
+
+fn max_dimension(dimension: Option<u32>) -> i32 {
+    dimension.map_or(i32::MAX, |dimension| dimension.min(i32::MAX as u32) as i32)
+}
+
+/// Map the buffer as a video frame, and copy it to an image with the privacy masks applied.
+fn buffer_to_image(
+    buffer: &gst::BufferRef,
+    video_info: &gst_video::VideoInfo,
+    masks: &PrivacyMasks,
+) -> anyhow::Result<DynamicImage> {
+    // Map the buffer as a video frame to respect the stride of the rows
+    let video_frame = gst_video::VideoFrameRef::from_buffer_ref_readable(buffer, video_info)
+        .map_err(|_| anyhow::anyhow!("failed to map the buffer"))?;
+    let mut image = video_frame_to_image(&video_frame).context("not a valid RGB image")?;
+    masks.apply(&mut image);
+
+    Ok(DynamicImage::ImageRgb8(image))
+}
+
+/// Copy an RGB video frame to an image.
+///
+/// The rows of the frame may be padded, so they are copied one by one according to the stride.
+fn video_frame_to_image(frame: &gst_video::VideoFrameRef<&gst::BufferRef>) -> Option<RgbImage> {
+    let width = frame.width();
+    let height = frame.height();
+    let stride = usize::try_from(*frame.plane_stride().first()?).ok()?;
+    let data = frame.plane_data(0).ok()?;
+
+    let row_size = width as usize * 3;
+    let mut pixels = Vec::with_capacity(row_size * height as usize);
+    for row in data.chunks(stride).take(height as usize) {
+        pixels.extend_from_slice(row.get(..row_size)?);
+    }
+
+    RgbImage::from_raw(width, height, pixels)
+}
+
+/// Get the time when the frame was captured.
+///
+/// It prefers the NTP time from the RTCP sender reports of the camera, and falls back
+/// to the running time of the frame on the (wall clock) pipeline clock. Returns `None`
+/// without the base time, e.g. for a file played with [`Pace::Fast`].
+fn capture_time(
+    buffer: &gst::BufferRef,
+    running_time: Option<gst::ClockTime>,
+    base_time: Option<gst::ClockTime>,
+) -> Option<SystemTime> {
+    let ntp_caps = gst::Caps::new_empty_simple("timestamp/x-ntp");
+    let ntp_time = buffer
+        .iter_meta::<gst::ReferenceTimestampMeta>()
+        .find(|meta| meta.reference().can_intersect(&ntp_caps))
+        .map(|meta| meta.timestamp());
+
+    if let Some(ntp_time) = ntp_time {
+        return SystemTime::UNIX_EPOCH
+            .checked_add(Duration::from_nanos(ntp_time.nseconds()))?
+            .checked_sub(Duration::from_secs(NTP_UNIX_OFFSET_SECS));
+    }
+
+    let clock_time = base_time? + running_time?;
+    SystemTime::UNIX_EPOCH.checked_add(Duration::from_nanos(clock_time.nseconds()))
+}